@@ -2,6 +2,14 @@
 use sys::io;
 
 mod cgroup;
+mod opts;
+mod privs;
+mod stats;
+mod output;
+mod trace;
+mod cpu;
+mod procs;
+mod perf;
 
 #[path = "sys/mod.rs"]
 pub mod sys;
@@ -22,11 +30,295 @@ fn start(argc: int, argv: **u8, _cm: *u8) -> int {
         args.iter().map(|&p| std::str::raw::from_c_str(p as *i8)).to_owned_vec()
     };
 
-    let cmdo = args.slice_from(1).connect(" ");
+    if args.len() > 1 && args[1] == ~"convert" {
+        return trace::convert(args.slice_from(2));
+    }
+
+    let opts = opts::parse(args.slice_from(1));
+
+    let creds = privs::resolve(opts.user, opts.group.as_ref().map(|g| g.as_slice()));
+
+    let mut subsys = ~["cpuacct", "memory"];
+    if cgroup::detect() == cgroup::V1 && cgroup::available("freezer") {
+        subsys.push("freezer");
+    }
+    if opts.io {
+        subsys.push("blkio");
+    }
+    if opts.cpu_quota.is_some() {
+        subsys.push("cpu");
+    }
+    let io_pressure = opts.pressure.iter().any(|r| r.equiv(&"io"))
+        || opts.stall_triggers.iter().any(|&(ref r, _, _, _)| r.equiv(&"io"));
+    if io_pressure && !subsys.contains(&"blkio") {
+        subsys.push("blkio");
+    }
+    if opts.perf.len() > 0 && opts.perf_cgroup && cgroup::detect() == cgroup::V1 {
+        subsys.push("perf_event");
+    }
+    if opts.cpus.is_some() || opts.mems.is_some() {
+        subsys.push("cpuset");
+    }
+    if cgroup::available("pids") {
+        subsys.push("pids");
+    } else if opts.max_pids.is_some() {
+        sys::fail("--max-pids needs the pids controller, which is not available");
+    }
+    if cgroup::detect() != cgroup::V2
+            && (opts.pressure.len() > 0 || opts.stall_triggers.len() > 0) {
+        sys::fail("--pressure and --stall-trigger need cgroup2");
+    }
+    for &(ref s, _) in opts.monitors.iter() {
+        let s : &str = *s;
+        if !subsys.contains(&s) {
+            subsys.push(s);
+        }
+    }
+    for &(ref s, _, _) in opts.stats.iter() {
+        let s : &str = *s;
+        if !subsys.contains(&s) {
+            subsys.push(s);
+        }
+    }
+
+    for i in range(0, opts.warmup) {
+        io::println(fmt!("Warmup %u/%u", i + 1, opts.warmup));
+        run(&opts, &creds, subsys, "/dev/null");
+    }
+
+    let mut results = ~[];
+    for i in range(0, opts.runs) {
+        let output = if opts.runs == 1 {
+            opts.output.clone()
+        } else {
+            run_output(opts.output, i + 1)
+        };
+
+        let res = run(&opts, &creds, subsys, output);
+        if opts.runs > 1 {
+            io::println(fmt!("Run %u/%u:", i + 1, opts.runs));
+        }
+        for line in res.summary().iter() {
+            io::println(*line);
+        }
+        results.push(res);
+    }
+
+    if opts.runs > 1 {
+        print_summary(results);
+    }
+
+    match results.iter().find(|r| r.code != 0) {
+        Some(r) => r.code,
+        None => 0
+    }
+}
+
+/// What was measured over a single execution of the command
+pub struct RunResult {
+    code: int,
+    status: ~str,
+    timed_out: bool,
+    wall_ns: u64,
+    cpu_ns: u64,
+    user_ns: u64,
+    sys_ns: u64,
+    peak_mem: int,
+    mean_mem: f64,
+    failcnt: Option<int>,
+    peak_tasks: Option<int>,
+    pids_max_hits: Option<int>,
+    nr_throttled: Option<int>,
+    throttled_ns: Option<u64>,
+    /// The memory of each command when the group's memory use peaked
+    peak_procs: ~[procs::ProcMem],
+    /// The final count of each perf event
+    perf: ~[(&'static str, u64)],
+    /// When the OOM killer was first invoked, relative to the start of the run
+    oom_time: Option<u64>
+}
+
+impl RunResult {
+    /// The summary block printed after the run and written to the log footer
+    pub fn summary(&self) -> ~[~str] {
+        let mut lines = ~[
+            fmt!("Exit status: %s%s", self.status, if self.timed_out { " (timed out)" } else { "" }),
+            fmt!("Peak memory: %d bytes", self.peak_mem),
+            fmt!("Mean memory: %.0f bytes", self.mean_mem),
+            fmt!("CPU time: %.3fs (user %.3fs, system %.3fs)", self.cpu_ns as f64 / 1e9,
+                 self.user_ns as f64 / 1e9, self.sys_ns as f64 / 1e9),
+            fmt!("Wall time: %.3fs", self.wall_ns as f64 / 1e9)
+        ];
+        match self.failcnt {
+            Some(n) => lines.push(fmt!("Memory limit hit: %d times", n)),
+            None => ()
+        }
+        match self.oom_time {
+            Some(t) => lines.push(fmt!("OOM killed at %.3fs", t as f64 / 1e9)),
+            None => ()
+        }
+        match self.peak_tasks {
+            Some(n) => lines.push(fmt!("Peak tasks: %d", n)),
+            None => ()
+        }
+        match self.pids_max_hits {
+            Some(n) if n > 0 => lines.push(fmt!("Task limit hit: %d times", n)),
+            _ => ()
+        }
+        match (self.nr_throttled, self.throttled_ns) {
+            (Some(n), Some(t)) => lines.push(fmt!("CPU throttled: %d periods, %.3fs", n,
+                                                  t as f64 / 1e9)),
+            _ => ()
+        }
+        if self.perf.len() > 0 {
+            lines.push(~"Perf counters:");
+            for &(name, count) in self.perf.iter() {
+                lines.push(fmt!("  %s: %u", name, count as uint));
+            }
+        }
+        if self.peak_procs.len() > 0 {
+            lines.push(~"Memory by command at peak:");
+            for p in self.peak_procs.iter().take(5) {
+                lines.push(fmt!("  %s (%u processes): RSS %u kB, PSS %u kB, anon %u kB, swap %u kB",
+                                p.name, p.procs, p.rss_kb, p.pss_kb, p.anon_kb, p.swap_kb));
+            }
+        }
+        lines
+    }
+
+    /// The summary as named values, for the log footer
+    pub fn fields(&self) -> ~[(&'static str, output::Value)] {
+        let mut fields = ~[
+            ("exit_code", output::Int(self.code as i64)),
+            ("exit_status", output::Str(self.status.clone())),
+            ("timed_out", output::Bool(self.timed_out)),
+            ("peak_memory_bytes", output::Int(self.peak_mem as i64)),
+            ("mean_memory_bytes", output::Float(self.mean_mem)),
+            ("cpu_time_ns", output::Int(self.cpu_ns as i64)),
+            ("user_time_ns", output::Int(self.user_ns as i64)),
+            ("system_time_ns", output::Int(self.sys_ns as i64)),
+            ("wall_time_ns", output::Int(self.wall_ns as i64)),
+            ("memory_failcnt", match self.failcnt {
+                Some(n) => output::Int(n as i64),
+                None => output::Null
+            }),
+            ("oom_killed", output::Bool(self.oom_time.is_some())),
+            ("oom_time_ns", match self.oom_time {
+                Some(t) => output::Int(t as i64),
+                None => output::Null
+            }),
+            ("peak_tasks", match self.peak_tasks {
+                Some(n) => output::Int(n as i64),
+                None => output::Null
+            }),
+            ("pids_max_hits", match self.pids_max_hits {
+                Some(n) => output::Int(n as i64),
+                None => output::Null
+            }),
+            ("nr_throttled", match self.nr_throttled {
+                Some(n) => output::Int(n as i64),
+                None => output::Null
+            }),
+            ("throttled_time_ns", match self.throttled_ns {
+                Some(t) => output::Int(t as i64),
+                None => output::Null
+            }),
+            ("peak_command", match self.peak_procs.head_opt() {
+                Some(p) => output::Str(p.name.clone()),
+                None => output::Null
+            }),
+            ("peak_command_pss_kb", match self.peak_procs.head_opt() {
+                Some(p) => output::Int(p.pss_kb as i64),
+                None => output::Null
+            })
+        ];
+        for &(name, count) in self.perf.iter() {
+            fields.push((name, output::Int(count as i64)));
+        }
+        fields
+    }
+}
+
+/// The affinity mask with the bits for `cpus` set
+fn cpu_mask(cpus: &[uint]) -> ~[u64] {
+    let words = cpus.iter().fold(0, |m, &c| if c > m { c } else { m }) / 64 + 1;
+    let mut mask = std::vec::from_elem(words, 0u64);
+    for &c in cpus.iter() {
+        mask[c / 64] |= 1u64 << (c % 64);
+    }
+    mask
+}
+
+/// The trace file for run `n` of several, `mem.csv` becomes `mem-n.csv`
+fn run_output(output: &str, n: uint) -> ~str {
+    let dir = match output.rfind('/') {
+        Some(i) => i + 1,
+        None => 0
+    };
+    match output.rfind('.') {
+        Some(dot) if dot > dir => fmt!("%s-%u%s", output.slice_to(dot), n, output.slice_from(dot)),
+        _ => fmt!("%s-%u", output, n)
+    }
+}
+
+fn print_summary(results: &[RunResult]) {
+    let rows : [(&str, ~[f64]), ..4] = [
+        ("wall time (s)", results.iter().map(|r| r.wall_ns as f64 / 1e9).collect()),
+        ("cpu time (s)", results.iter().map(|r| r.cpu_ns as f64 / 1e9).collect()),
+        ("peak memory (bytes)", results.iter().map(|r| r.peak_mem as f64).collect()),
+        ("mean memory (bytes)", results.iter().map(|r| r.mean_mem).collect()),
+    ];
+
+    io::println(fmt!("Summary of %u runs:", results.len()));
+    for &(name, ref samples) in rows.iter() {
+        let s = stats::summarize(*samples);
+        io::println(fmt!("  %s: mean %.3f, median %.3f, stddev %.3f, min %.3f, max %.3f, 95%% CI %.3f..%.3f",
+                         name, s.mean, s.median, s.stddev, s.min, s.max,
+                         s.mean - s.ci95, s.mean + s.ci95));
+    }
+}
+
+/**
+ * Runs the command once in a fresh cgroup, logging to `output`.
+ */
+fn run(opts: &opts::Options, creds: &privs::Credentials, subsys: &[&str], output: &str) -> RunResult {
+    let cmdo = opts.command.connect(" ");
     let cmd : &str = cmdo;
     let c : &str = "-c";
 
-    let mut group = cgroup::Group::new(~"bench", ["cpuacct", "memory"]);
+    let mut group = match opts.group_name {
+        Some(ref name) => match cgroup::Group::create(name.clone(), subsys, opts.exclusive) {
+            sys::err::Ok(g) => g,
+            sys::err::Err(e) => sys::fail(fmt!("Could not create cgroup %s (%s)", *name, e.msg()))
+        },
+        None => cgroup::Group::new_unique("bench", subsys)
+    };
+
+    // Opened before forking, so a file missing from this hierarchy is
+    // reported before the command can start
+    let mem_usage = group.monitor("memory", "memory.usage_in_bytes");
+    if opts.memsw_limit.is_some() {
+        match group.try_monitor("memory", "memory.memsw.limit_in_bytes") {
+            sys::err::Ok(_) => (),
+            sys::err::Err(e) => sys::fail(fmt!("--memsw-limit needs swap accounting (%s)", e.msg()))
+        }
+    }
+
+    let monitors = opts.monitors.iter().map(|&(ref s, ref f)| {
+        match group.try_monitor(*s, *f) {
+            sys::err::Ok(m) => m,
+            sys::err::Err(e) => {
+                let hint = if group.hierarchy() == cgroup::V2 {
+                    ", it may only exist on cgroup v1"
+                } else {
+                    ""
+                };
+                sys::fail(fmt!("Can't monitor %s:%s (%s)%s", *s, *f, e.msg(), hint))
+            }
+        }
+    }).collect();
+
+    let affinity = opts.cpus.as_ref().map(|c| cpu_mask(cgroup::parse_list(*c).unwrap()));
 
     let mut pipes = (0,0);
     let res = sys::pipe(&mut pipes);
@@ -36,107 +328,616 @@ fn start(argc: int, argv: **u8, _cm: *u8) -> int {
 
     let (read, write) = pipes;
 
+    // With SIGPIPE blocked, releasing a child that died before reading the
+    // pipe fails with EPIPE instead of killing grease-bench before teardown.
+    // SIGCHLD is received through a signalfd, to notice the child exiting
+    // without waiting for the next sample.
+    let signals = sys::sig::set([sys::sig::PIPE, sys::sig::CHLD]);
+    let err = sys::sig::block(signals);
+    if err.is_err() {
+        sys::fail(fmt!("Could not block signals (%s)", err.msg()));
+    }
+
     let pid = sys::fork();
     if pid == 0 {
         io::raw::close(write);
+        sys::sig::unblock(signals);
+
+        match affinity {
+            Some(ref mask) => {
+                let err = sys::sched_setaffinity(0, *mask);
+                if err.is_err() {
+                    sys::fail(fmt!("Could not set CPU affinity (%s)", err.msg()));
+                }
+            }
+            None => ()
+        }
 
-        sys::setuid(1000);
-        sys::setgid(1000);
+        privs::drop_to(creds);
 
+        // The parent closes the pipe without writing to it if it fails
+        // setting up the group, the command must not run unsupervised then
         let mut buf = [0];
-        io::raw::read(read, buf);
+        let n = io::raw::read(read, buf);
+        if n != 1 {
+            sys::exit(1);
+        }
 
         io::raw::close(read);
         let res = sys::execve("/bin/sh", [c, cmd], env);
 
-        sys::fail(res.msg());
+        sys::fail(res.msg())
 
     } else {
         io::raw::close(read);
 
         group.set_bool("memory", "memory.use_hierarchy", true);
+        group.set_memory_limits(opts.memory_limit, opts.memsw_limit);
+        if group.has_subsys("cpuset") {
+            group.set_cpuset(opts.cpus.as_ref().map(|c| c.as_slice()),
+                             opts.mems.as_ref().map(|m| m.as_slice()));
+        }
+        match opts.cpu_quota {
+            Some(q) => group.set_cpu_quota(q),
+            None => ()
+        }
+        match opts.max_pids {
+            Some(n) => group.set_max_pids(n),
+            None => ()
+        }
+        let mut oom = group.oom_watch();
         group.add_task(pid);
 
-        let mem_usage = group.monitor("memory", "memory.usage_in_bytes");
+        let format = match opts.format {
+            Some(ref f) => output::by_name(*f).unwrap(),
+            None => output::for_path(output)
+        };
 
-        let mut logger = Logger::new("mem.csv", mem_usage, ~[]);
+        let mut logger = Logger::new(output, format, mem_usage, monitors);
 
-        logger.add_comment("Timestamp (ns), memory usage");
+        for &(ref s, ref f, ref keys) in opts.stats.iter() {
+            logger.add_stat(group.stat_monitor(*s, *f, keys.clone()));
+        }
 
-        io::raw::write(write, ['0' as u8]);
+        if opts.cpu {
+            logger.add_cpu(cpu::CpuSampler::new(&group));
+        }
+        if opts.io {
+            logger.add_io(group.io_monitor());
+        }
+        // The child is still waiting on the pipe, so in inherit mode the
+        // counters are in place before it execs
+        if opts.perf.len() > 0 {
+            logger.add_perf(if opts.perf_cgroup {
+                perf::Counters::for_group(opts.perf, &group)
+            } else {
+                perf::Counters::for_process(opts.perf, pid)
+            });
+        }
+        if opts.procs {
+            logger.add_procs(procs::ProcSampler::new(&group));
+        }
+        for r in opts.pressure.iter() {
+            logger.add_pressure(group.pressure_monitor(*r));
+        }
+        if opts.cpu_quota.is_some() {
+            let throttled = match group.hierarchy() {
+                cgroup::V1 => ~"throttled_time",
+                cgroup::V2 => ~"throttled_usec"
+            };
+            logger.add_stat(group.stat_monitor("cpu", "cpu.stat", ~[~"nr_throttled", throttled]));
+        }
+        if group.has_subsys("pids") {
+            logger.add_pids(group.monitor("pids", "pids.current"));
+        }
+
+        logger.set_flush_interval(1_000_000_000);
+
+        let mut timer = if opts.interval > 0 {
+            match sys::timer::Timer::new(opts.interval as u64 * 1000) {
+                sys::err::Ok(t) => Some(t),
+                sys::err::Err(e) => sys::fail(fmt!("Can't create timer (%s)", e.msg()))
+            }
+        } else {
+            None
+        };
+
+        // Token 0 is the sampling timer, 1 the child exiting, the thresholds
+        // are numbered from 2 and the stall triggers follow them
+        let epoll = match sys::event::Epoll::new() {
+            sys::err::Ok(e) => e,
+            sys::err::Err(e) => sys::fail(fmt!("Can't create epoll instance (%s)", e.msg()))
+        };
+        match timer {
+            Some(ref t) => { epoll.add(t.fd(), sys::event::EPOLLIN, 0); }
+            None => ()
+        }
+        let child = match sys::event::SignalFd::new(sys::sig::set([sys::sig::CHLD])) {
+            sys::err::Ok(s) => s,
+            sys::err::Err(e) => sys::fail(fmt!("Can't create signalfd (%s)", e.msg()))
+        };
+        epoll.add(child.fd(), sys::event::EPOLLIN, 1);
+
+        let mut thresholds = ~[];
+        for &bytes in opts.thresholds.iter() {
+            match group.memory_threshold(bytes) {
+                sys::err::Ok(ev) => {
+                    epoll.add(ev.fd(), sys::event::EPOLLIN, thresholds.len() as u64 + 2);
+                    thresholds.push((bytes, ev));
+                }
+                sys::err::Err(e) => {
+                    io::println(fmt!("Warning could not register threshold %u (%s)",
+                                     bytes as uint, e.msg()));
+                }
+            }
+        }
+
+        let mut triggers = ~[];
+        for &(ref res, ref kind, stall, window) in opts.stall_triggers.iter() {
+            match group.pressure_trigger(*res, *kind, stall * 1000, window * 1000) {
+                sys::err::Ok(t) => {
+                    let token = thresholds.len() + triggers.len() + 2;
+                    epoll.add(t.fd(), sys::event::EPOLLPRI, token as u64);
+                    triggers.push((fmt!("%s %s stall of %ums within %ums", *res, *kind,
+                                        stall, window), t));
+                }
+                sys::err::Err(e) => {
+                    io::println(fmt!("Warning could not register %s stall trigger (%s)",
+                                     *res, e.msg()));
+                }
+            }
+        }
+
+        let info = output::RunInfo {
+            command: opts.command.clone(),
+            env: env.iter().map(|e| e.to_owned()).collect(),
+            cgroup: group.name().to_owned(),
+            interval_us: opts.interval,
+            columns: logger.columns()
+        };
+        logger.begin(&info);
+
+        // The child exits with the reason if it failed to start, it gets
+        // reaped as usual below
+        if io::raw::write(write, ['0' as u8]) < 0 {
+            logger.add_comment("The command failed to start");
+        }
         io::raw::close(write);
 
+        let mut status = None;
+        let mut wall_ns = None;
+
+        let start_time = sys::getclock();
+        let timeout = opts.timeout as u64 * 1_000_000_000;
+        let grace = opts.kill_grace as u64 * 1_000_000_000;
+        let mut term_sent : Option<u64> = None;
+        let mut kill_sent = false;
+        let mut oom_time : Option<u64> = None;
+
         loop {
-            let (_, ret) = sys::waitpid(pid, 1);
+            let (stat, ret) = sys::waitpid(pid, sys::WNOHANG);
             if ret < 0 {
                 if -1*ret == sys::err::EINTR {
                     break;
                 }
                 io::println(sys::err::msg(-1*ret));
             } else if ret > 0 {
+                status = Some(sys::ExitStatus::from_wait(stat));
+                wall_ns = Some(sys::getclock() - start_time);
                 break;
             }
 
             logger.log();
 
-            //sys::usleep(5000);
+            if oom.check() && oom_time.is_none() {
+                oom_time = Some(sys::getclock() - start_time);
+                logger.add_comment("OOM killer invoked");
+            }
+
+            if timeout > 0 && !kill_sent {
+                let now = sys::getclock();
+                match term_sent {
+                    None if now - start_time >= timeout => {
+                        let n = group.signal_all(sys::sig::TERM);
+                        logger.add_comment(fmt!("Timed out after %us, sent SIGTERM to %u tasks",
+                                                opts.timeout, n));
+                        term_sent = Some(now);
+                    }
+                    Some(t) if now - t >= grace => {
+                        let n = group.signal_all(sys::sig::KILL);
+                        if n > 0 {
+                            logger.add_comment(fmt!("Sent SIGKILL to %u remaining tasks", n));
+                        }
+                        kill_sent = true;
+                    }
+                    _ => ()
+                }
+            }
+
+            // Without a timer this just checks the thresholds and moves on
+            let mut ready = [0u64,..8];
+            let wait_ms = if timer.is_some() { -1 } else { 0 };
+            let n = match epoll.wait(ready, wait_ms) {
+                sys::err::Ok(n) => n,
+                sys::err::Err(e) => sys::fail(fmt!("Waiting for events failed (%s)", e.msg()))
+            };
+
+            for &token in ready.slice_to(n).iter() {
+                if token == 0 {
+                    match timer {
+                        Some(ref mut t) => { t.wait(); }
+                        None => ()
+                    }
+                } else if token == 1 {
+                    // Reaped by waitpid at the top of the loop
+                    child.drain();
+                } else if token as uint <= thresholds.len() + 1 {
+                    let (bytes, ref ev) = thresholds[token as uint - 2];
+                    if ev.fired() > 0 {
+                        logger.add_comment(fmt!("Memory usage crossed %u bytes", bytes as uint));
+                    }
+                } else {
+                    let (ref what, _) = triggers[token as uint - thresholds.len() - 2];
+                    logger.add_comment(*what);
+                }
+            }
+        }
+
+        match timer {
+            Some(ref t) => logger.add_comment(fmt!("Missed samples: %u", t.overruns() as uint)),
+            None => ()
         }
-    }
 
-    0
+        if oom_time.is_none() && oom.check() {
+            oom_time = Some(sys::getclock() - start_time);
+            logger.add_comment("OOM killer invoked");
+        }
+
+        let left = group.kill_all();
+        if left.len() > 0 {
+            logger.add_comment(fmt!("%u processes could not be killed", left.len()));
+        }
+
+        // Taken when the child was reaped, the teardown is not part of the run
+        let wall_ns = wall_ns.unwrap_or(sys::getclock() - start_time);
+
+        let (code, desc) = match status {
+            Some(st) => (st.code(), st.describe()),
+            None => (1, ~"unknown")
+        };
+
+        let usage = group.usage();
+
+        // The kernel's peak catches spikes between samples, if it has one
+        let peak_mem = match usage.peak_mem {
+            Some(p) if p > logger.peak() => p,
+            _ => logger.peak()
+        };
+        let peak_tasks = match (usage.peak_tasks, logger.peak_tasks()) {
+            (Some(k), Some(l)) if k > l => Some(k),
+            (_, Some(l)) => Some(l),
+            (k, None) => k
+        };
+
+        let res = RunResult {
+            code: code,
+            status: desc,
+            timed_out: term_sent.is_some(),
+            wall_ns: wall_ns,
+            cpu_ns: usage.cpu_ns,
+            user_ns: usage.user_ns,
+            sys_ns: usage.sys_ns,
+            peak_mem: peak_mem,
+            mean_mem: logger.mean(),
+            failcnt: usage.failcnt,
+            peak_tasks: peak_tasks,
+            pids_max_hits: usage.pids_max_hits,
+            nr_throttled: usage.nr_throttled,
+            throttled_ns: usage.throttled_ns,
+            peak_procs: logger.peak_procs(),
+            perf: logger.perf_totals(),
+            oom_time: oom_time
+        };
+
+        logger.finish(res.fields());
+
+        res
+    }
 }
 
 pub struct Logger {
-    file: io::File,
+    file: io::BufferedFile,
+    format: ~output::Format,
     primary_mon: cgroup::Monitor,
     monitors: ~[cgroup::Monitor],
+    stats: ~[cgroup::StatMonitor],
+    cpu: Option<cpu::CpuSampler>,
+    io: Option<cgroup::IoMonitor>,
+    pressure: ~[cgroup::PressureMonitor],
+    procs: Option<procs::ProcSampler>,
+    perf: Option<perf::Counters>,
+    pids: Option<cgroup::Monitor>,
+    tasks: Option<int>,
+    peak_tasks: Option<int>,
+    row: ~[Option<int>],
     prev_val: Option<int>,
-    start_time: u64
+    start_time: u64,
+    peak: int,
+    total: f64,
+    nsamples: uint
 }
 
 impl Logger {
-    pub fn new(log_file: &str, primary_mon: cgroup::Monitor,
+    pub fn new(log_file: &str, format: ~output::Format, primary_mon: cgroup::Monitor,
                 monitors: ~[cgroup::Monitor]) -> Logger {
         Logger {
-            file: io::File::open(log_file, "ws").unwrap(),
+            file: io::BufferedFile::open(log_file, "ws").unwrap(),
+            format: format,
             primary_mon: primary_mon,
             monitors: monitors,
+            stats: ~[],
+            cpu: None,
+            io: None,
+            pressure: ~[],
+            procs: None,
+            perf: None,
+            pids: None,
+            tasks: None,
+            peak_tasks: None,
+            row: ~[],
             prev_val: None,
-            start_time: 0
+            start_time: 0,
+            peak: 0,
+            total: 0.0,
+            nsamples: 0
         }
     }
 
-    pub fn add_comment(&self, str: &str) {
-        self.file.write_str("# ");
-        self.file.write_str(str);
-        self.file.write_str("\n");
+    /// Adds columns for the selected keys of a `key value` stat file
+    pub fn add_stat(&mut self, stat: cgroup::StatMonitor) {
+        self.stats.push(stat);
+    }
+
+    /// Adds CPU time and utilisation columns
+    pub fn add_cpu(&mut self, cpu: cpu::CpuSampler) {
+        self.cpu = Some(cpu);
+    }
+
+    /// Adds block I/O columns
+    pub fn add_io(&mut self, io: cgroup::IoMonitor) {
+        self.io = Some(io);
+    }
+
+    /// Adds columns for the total some and full stall time of a resource
+    pub fn add_pressure(&mut self, pressure: cgroup::PressureMonitor) {
+        self.pressure.push(pressure);
+    }
+
+    /// Adds columns for the memory of the group's processes from /proc
+    pub fn add_procs(&mut self, procs: procs::ProcSampler) {
+        self.procs = Some(procs);
+    }
+
+    /// Adds a column for each perf event
+    pub fn add_perf(&mut self, perf: perf::Counters) {
+        self.perf = Some(perf);
+    }
+
+    /// Adds a column for the number of tasks in the group, which is also
+    /// checked on every sample for the peak task count
+    pub fn add_pids(&mut self, pids: cgroup::Monitor) {
+        self.pids = Some(pids);
+    }
+
+    /// Writes the log out at least every `interval_ns` nanoseconds, rather than
+    /// only when the buffer fills up.
+    pub fn set_flush_interval(&mut self, interval_ns: u64) {
+        self.file.set_flush_policy(io::BUF_SIZE, interval_ns);
+    }
+
+    /// The names of the logged columns, each named after the file it was
+    /// read from.
+    pub fn columns(&self) -> ~[~str] {
+        let mut cols = ~[self.primary_mon.name().to_owned()];
+
+        for m in self.monitors.iter() {
+            cols.push(m.name().to_owned());
+        }
+
+        for st in self.stats.iter() {
+            for k in st.keys().iter() {
+                cols.push(st.name() + "." + *k);
+            }
+        }
+
+        match self.cpu {
+            Some(ref c) => cols.push_all_move(c.columns()),
+            None => ()
+        }
+
+        if self.io.is_some() {
+            cols.push_all_move(cgroup::IoMonitor::columns());
+        }
+
+        for p in self.pressure.iter() {
+            cols.push(p.name() + ".pressure.some_us");
+            cols.push(p.name() + ".pressure.full_us");
+        }
+
+        if self.procs.is_some() {
+            cols.push_all_move(procs::ProcSampler::columns());
+        }
+
+        match self.perf {
+            Some(ref p) => cols.push_all_move(p.columns()),
+            None => ()
+        }
+
+        match self.pids {
+            Some(ref m) => cols.push(m.name().to_owned()),
+            None => ()
+        }
+
+        cols
+    }
+
+    /// Writes the run metadata, must be called before the first sample
+    pub fn begin(&mut self, info: &output::RunInfo) {
+        self.format.begin(&mut self.file, info);
+    }
+
+    pub fn add_comment(&mut self, str: &str) {
+        let tm = if self.start_time == 0 { 0 } else { sys::getclock() - self.start_time };
+        self.format.note(&mut self.file, tm, str);
+    }
+
+    /// Writes the run summary, nothing should be logged after this
+    pub fn finish(&mut self, summary: &[(&str, output::Value)]) {
+        self.format.finish(&mut self.file, summary);
+    }
+
+    /// The highest value of the primary monitor seen so far
+    pub fn peak(&self) -> int {
+        self.peak
+    }
+
+    /// The memory of each command when the processes' PSS peaked, if the
+    /// processes were sampled
+    pub fn peak_procs(&self) -> ~[procs::ProcMem] {
+        match self.procs {
+            Some(ref p) => p.peak(),
+            None => ~[]
+        }
+    }
+
+    /// The current count of each perf event
+    pub fn perf_totals(&self) -> ~[(&'static str, u64)] {
+        match self.perf {
+            Some(ref p) => p.totals(),
+            None => ~[]
+        }
+    }
+
+    /// The highest number of tasks seen in the group, if it is monitored
+    pub fn peak_tasks(&self) -> Option<int> {
+        self.peak_tasks
+    }
+
+    /// The mean value of the primary monitor over every sample taken
+    pub fn mean(&self) -> f64 {
+        if self.nsamples == 0 {
+            0.0
+        } else {
+            self.total / self.nsamples as f64
+        }
     }
 
     pub fn log(&mut self) {
-        let val = Some(self.primary_mon.get_int());
-        if val != self.prev_val {
+        let v = self.primary_mon.get_int();
+        if v > self.peak {
+            self.peak = v;
+        }
+        self.total += v as f64;
+        self.nsamples += 1;
+
+        match self.pids {
+            Some(ref m) => {
+                let n = m.get_int();
+                self.tasks = Some(n);
+                if self.peak_tasks.map_default(true, |&p| n > p) {
+                    self.peak_tasks = Some(n);
+                }
+            }
+            None => ()
+        }
+
+        let val = Some(v);
+        if val != self.prev_val || self.every_sample() {
             self.prev_val = val;
             self.write_log();
         }
     }
 
+    /// Whether a row has to be written for every sample, rather than only
+    /// when memory usage changes. That is the case as soon as any other
+    /// series is logged, as it can change while memory usage stays flat.
+    fn every_sample(&self) -> bool {
+        self.monitors.len() > 0 || self.stats.len() > 0 || self.cpu.is_some()
+            || self.io.is_some() || self.pressure.len() > 0 || self.procs.is_some()
+            || self.perf.is_some() || self.pids.is_some()
+    }
+
     fn write_log(&mut self) {
-        let mut tm = sys::getclock();
+        let now = sys::getclock();
 
         if self.start_time == 0 {
-            self.start_time = tm;
+            self.start_time = now;
         }
-        tm -= self.start_time;
+        let tm = now - self.start_time;
 
-        self.file.write_str(tm.to_str());
-        self.file.write_str(",");
-        self.file.write_str(self.prev_val.unwrap().to_str());
+        self.row.clear();
+        self.row.push(self.prev_val);
 
         for m in self.monitors.iter() {
-            self.file.write_str(",");
-            self.file.write_str(m.get_int().to_str());
+            self.row.push(Some(m.get_int()));
+        }
+
+        for st in self.stats.mut_iter() {
+            st.sample();
+            for v in st.values().iter() {
+                self.row.push(*v);
+            }
+        }
+
+        match self.cpu {
+            Some(ref mut c) => c.sample(now, &mut self.row),
+            None => ()
+        }
+
+        match self.io {
+            Some(ref mut m) => {
+                let st = m.sample();
+                self.row.push(Some(st.read_bytes as int));
+                self.row.push(Some(st.write_bytes as int));
+                self.row.push(Some(st.read_ops as int));
+                self.row.push(Some(st.write_ops as int));
+            }
+            None => ()
+        }
+
+        for p in self.pressure.mut_iter() {
+            let psi = p.sample();
+            self.row.push(Some(psi.some.total_us as int));
+            self.row.push(psi.full.map(|f| f.total_us as int));
+        }
+
+        match self.procs {
+            Some(ref mut p) => p.sample(&mut self.row),
+            None => ()
+        }
+
+        match self.perf {
+            Some(ref p) => p.sample(&mut self.row),
+            None => ()
+        }
+
+        if self.pids.is_some() {
+            self.row.push(self.tasks);
+        }
+
+        self.format.sample(&mut self.file, tm, self.row);
+
+        // The breakdown by command follows the totals it adds up to
+        match self.procs {
+            Some(ref p) => {
+                let note = p.note();
+                if note.len() > 0 {
+                    self.format.note(&mut self.file, tm, note);
+                }
+            }
+            None => ()
         }
 
-        self.file.write_str("\n");
+        self.file.flush_if_due(now);
     }
 }
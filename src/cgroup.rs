@@ -1,63 +1,306 @@
 
 use sys;
 use sys::io;
+use sys::err::{ErrorResult, Ok, Err};
+use sys::event::{EventFd, EFD_NONBLOCK, EFD_CLOEXEC};
 use std::libc::pid_t;
 
 static CGPATH : &'static str = "/sys/fs/cgroup/";
+// Only root can create files in /run, so nobody can plant a symlink there
+static LOCKDIR : &'static str = "/run/grease-bench/";
+
+// cpuacct.stat counts in USER_HZ ticks, which is 100 on all architectures
+static NSEC_PER_TICK : u64 = 10_000_000;
+
+/// Which cgroup hierarchy is mounted at `CGPATH`.
+#[deriving(Eq)]
+pub enum Hierarchy {
+    /// One hierarchy per controller, `/sys/fs/cgroup/<subsys>/<name>`
+    V1,
+    /// The unified cgroup2 hierarchy, `/sys/fs/cgroup/<name>`
+    V2
+}
+
+/**
+ * Maps v1 controller files to their cgroup2 equivalents. An empty
+ * replacement means the file has no equivalent because the behaviour is
+ * always on in cgroup2. Files not listed have the same name in both.
+ */
+static V2_FILES : &'static [(&'static str, &'static str)] = &[
+    ("memory.usage_in_bytes",       "memory.current"),
+    ("memory.max_usage_in_bytes",   "memory.peak"),
+    ("memory.limit_in_bytes",       "memory.max"),
+    ("memory.soft_limit_in_bytes",  "memory.low"),
+    ("memory.memsw.usage_in_bytes", "memory.swap.current"),
+    ("memory.memsw.limit_in_bytes", "memory.swap.max"),
+    ("memory.use_hierarchy",        ""),
+    ("tasks",                       "cgroup.procs"),
+];
 
 struct Group {
     name: ~str,
+    hier: Hierarchy,
     subsys: ~[~str],
-    tasks: ~[pid_t]
+    /// Whether this run made the directory of each controller in `subsys`
+    made: ~[bool],
+    tasks: ~[pid_t],
+    lock: Option<io::File>
 }
 
 struct Monitor {
+    priv name: ~str,
+    priv file: io::File
+}
+
+/// Resource usage totals of a group, see `Group::usage`
+pub struct Usage {
+    peak_mem: Option<int>,
+    failcnt: Option<int>,
+    cpu_ns: u64,
+    user_ns: u64,
+    sys_ns: u64,
+    /// The kernel's peak task count, only on v2 kernels with `pids.peak`
+    peak_tasks: Option<int>,
+    /// How many forks failed because of `pids.max`
+    pids_max_hits: Option<int>,
+    /// How many periods the group was throttled in by its CPU quota, and for
+    /// how long in total, if the cpu controller is used
+    nr_throttled: Option<int>,
+    throttled_ns: Option<u64>
+}
+
+/**
+ * A notification registered through a v1 group's `cgroup.event_control`. The
+ * file the event is about is kept open for as long as the registration.
+ */
+pub struct Event {
+    priv efd: EventFd,
+    priv target: io::File
+}
+
+/**
+ * Detects the group's tasks being killed by the OOM killer, using an
+ * `memory.oom_control` event on v1 and the `oom_kill` count in
+ * `memory.events` on v2.
+ */
+pub struct OomWatch {
+    priv event: Option<Event>,
+    priv events: Option<StatMonitor>,
+    priv kills: int
+}
+
+/**
+ * Monitors a file of `key value` lines, such as `memory.stat`, and records
+ * the values of a chosen set of keys. The read buffer and the value slots are
+ * allocated once, taking a sample does not allocate.
+ */
+struct StatMonitor {
+    priv name: ~str,
+    priv file: io::File,
+    priv buf: ~[u8],
+    priv keys: ~[~str],
+    priv values: ~[Option<int>]
+}
+
+/**
+ * One line of a PSI file: the share of time, in percent, that some or all of
+ * the group's tasks were stalled over the last 10s, 60s and 300s, and the
+ * total stall time.
+ */
+#[deriving(Clone)]
+pub struct PsiLine {
+    avg10: f64,
+    avg60: f64,
+    avg300: f64,
+    total_us: u64
+}
+
+/// The contents of a cgroup2 `*.pressure` file, `full` is missing from
+/// `cpu.pressure` on older kernels
+#[deriving(Clone)]
+pub struct Pressure {
+    some: PsiLine,
+    full: Option<PsiLine>
+}
+
+/// Monitors the pressure stall information of one resource
+pub struct PressureMonitor {
+    priv name: ~str,
+    priv file: io::File,
+    priv buf: ~[u8]
+}
+
+/**
+ * A PSI trigger, the kernel signals it with EPOLLPRI whenever the group's
+ * tasks are stalled on a resource for longer than a threshold within a time
+ * window. The trigger is removed when the file is closed.
+ */
+pub struct PressureTrigger {
     priv file: io::File
 }
 
+/// Block I/O done by a group, summed over all devices
+#[deriving(Clone)]
+pub struct IoStats {
+    read_bytes: u64,
+    write_bytes: u64,
+    read_ops: u64,
+    write_ops: u64
+}
+
+/**
+ * Monitors the block I/O of a group. v1 spreads it over
+ * `blkio.throttle.io_service_bytes` and `blkio.throttle.io_serviced`, v2 has
+ * both in `io.stat`.
+ */
+pub struct IoMonitor {
+    priv hier: Hierarchy,
+    priv bytes: io::File,
+    priv ops: Option<io::File>,
+    priv buf: ~[u8]
+}
+
 impl Group {
+    /**
+     * Creates a group with a generated name that no other run can be using,
+     * see `unique_name`. The directories are created exclusively, so no lock
+     * is needed.
+     */
+    pub fn new_unique(prefix: &str, subsys: &[&str]) -> Group {
+        for _ in range(0, 10) {
+            match Group::make(unique_name(prefix), subsys, true, None) {
+                Ok(g) => return g,
+                Err(e) => {
+                    if !e.is(sys::err::EEXIST) {
+                        sys::fail(fmt!("Could not create directory (%s)", e.msg()));
+                    }
+                }
+            }
+        }
+        sys::fail("Could not find an unused cgroup name")
+    }
 
-    pub fn new(name: ~str, subsys: &[&str]) -> Group {
+    /**
+     * Creates the group. A lock file on the group name is held for as long as
+     * the group is alive, creation fails with EBUSY if another run holds it.
+     * A group directory left behind by an earlier run is reused, unless
+     * `exclusive` is set in which case creation fails with EEXIST. A group
+     * that has processes in it is never reused, it fails with ENOTEMPTY, as
+     * they would be killed at the end of the run.
+     */
+    pub fn create(name: ~str, subsys: &[&str], exclusive: bool) -> ErrorResult<Group> {
+        match lock_name(name) {
+            Ok(f) => Group::make(name, subsys, exclusive, Some(f)),
+            Err(e) => Err(e)
+        }
+    }
+
+    /// Creates the group's directories, holding `lock` until the group is
+    /// dropped
+    fn make(name: ~str, subsys: &[&str], exclusive: bool,
+            lock: Option<io::File>) -> ErrorResult<Group> {
         let mut g = Group {
             name: name,
+            hier: detect(),
             subsys: ~[],
-            tasks: ~[]
+            made: ~[],
+            tasks: ~[],
+            lock: lock
         };
 
         for &subsys in subsys.iter() {
             g.subsys.push(subsys.to_owned());
+            g.made.push(false);
+            let i = g.subsys.len() - 1;
 
-            let path = g.get_path(subsys, None);
-            let ret = io::mkdir(path, 438);
+            // cgroup2 has a single directory shared by every controller, and
+            // v1 controllers mounted together, such as cpu and cpuacct, share
+            // a directory as well
+            if g.owns_dir(i) {
+                let path = g.get_path(subsys, None);
+                let ret = io::mkdir(path, 438);
+
+                if ret.is_err() && (exclusive || !ret.is(sys::err::EEXIST)) {
+                    // Only the directories created so far get removed on drop
+                    g.subsys.pop();
+                    g.made.pop();
+                    return Err(ret);
+                }
+                g.made[i] = !ret.is_err();
+            }
+        }
 
-            if (ret.is_err() && !ret.is(sys::err::EEXIST)) {
-                sys::fail(fmt!("Could not create directory (%s)", ret.msg()));
+        // Whatever is in a reused group, e.g. one shared with other workloads
+        // or the root group, is not ours to kill
+        if !g.created() {
+            let busy = range(0, g.subsys.len()).any(|i| {
+                g.owns_dir(i) && g.pids_in(g.subsys[i]).len() > 0
+            });
+            if busy {
+                return Err(sys::err::Error::errno(sys::err::ENOTEMPTY));
             }
+        }
 
+        if g.hier == V2 {
+            g.enable_controllers();
         }
 
-        return g;
+        return Ok(g);
+    }
+
+    pub fn name<'a>(&'a self) -> &'a str {
+        self.name.as_slice()
+    }
+
+    pub fn hierarchy(&self) -> Hierarchy {
+        self.hier
     }
 
     pub fn add_task(&mut self, pid: pid_t) {
         self.tasks.push(pid);
-        for sys in self.subsys.iter() {
-            let fname = self.get_path(*sys, Some("tasks"));
-            let file = io::File::open(fname, "a").unwrap();
-            file.write_str(pid.to_str());
-            file.write_str("\n");
+        match self.hier {
+            V1 => {
+                for sys in self.subsys.iter() {
+                    let fname = self.get_path(*sys, Some("tasks"));
+                    let file = io::File::open(fname, "a").unwrap();
+                    file.write_str(pid.to_str());
+                    file.write_str("\n");
+                }
+            }
+            V2 => {
+                let fname = self.get_path(self.subsys[0], Some("cgroup.procs"));
+                let file = io::File::open(fname, "a").unwrap();
+                file.write_str(pid.to_str());
+                file.write_str("\n");
+            }
         }
     }
 
     pub fn set_bool(&self, subsys: &str, file: &str, val: bool) {
         let val = if val { "1\n" } else { "0\n" };
 
+        if !self.has_file(file) {
+            return;
+        }
         let fname = self.get_path(subsys, Some(file));
 
         let file = io::File::open(fname, "w").unwrap();
         file.write_str(val);
     }
 
+    /// Writes `val` to a controller file, returning the error if the file
+    /// could not be opened or written.
+    pub fn set_str(&self, subsys: &str, file: &str, val: &str) -> sys::err::Error {
+        let fname = self.get_path(subsys, Some(file));
+        match io::File::open(fname, "w") {
+            Ok(f) => match f.write_str(val) {
+                Ok(_) => sys::err::Error::ok(),
+                Err(e) => e
+            },
+            Err(e) => e
+        }
+    }
+
     pub fn get_int(&self, subsys: &str, value: &str) -> int {
         let fname = self.get_path(subsys, Some(value));
         let mut buf = [0u8,..32];
@@ -85,23 +328,551 @@ impl Group {
     }
 
     pub fn monitor(&self, subsys: &str, value: &str) -> Monitor {
+        match self.try_monitor(subsys, value) {
+            Ok(m) => m,
+            Err(e) => sys::fail(fmt!("Could not open %s (%s)", value, e.msg()))
+        }
+    }
+
+    /**
+     * Like `monitor`, but returns the error if the file can't be opened.
+     * Files that only exist on v1, such as `cpuacct.usage`, fail with ENOENT
+     * on v2.
+     */
+    pub fn try_monitor(&self, subsys: &str, value: &str) -> ErrorResult<Monitor> {
+        if file_name(self.hier, value).len() == 0 {
+            return Err(sys::err::Error::errno(sys::err::ENOENT));
+        }
+        match io::File::open(self.get_path(subsys, Some(value)), "r") {
+            Ok(file) => Ok(Monitor { name: value.to_owned(), file: file }),
+            Err(e) => Err(e)
+        }
+    }
+
+    pub fn io_monitor(&self) -> IoMonitor {
+        use std::vec;
+
+        let (bytes, ops) = match self.hier {
+            V1 => ("blkio.throttle.io_service_bytes", Some("blkio.throttle.io_serviced")),
+            V2 => ("io.stat", None)
+        };
+
+        let open = |f: &str| io::File::open(self.get_path("blkio", Some(f)), "r").unwrap();
+        IoMonitor {
+            hier: self.hier,
+            bytes: open(bytes),
+            ops: ops.map(|f| open(f)),
+            buf: vec::from_elem(4096, 0u8)
+        }
+    }
+
+    pub fn pressure_monitor(&self, resource: &str) -> PressureMonitor {
+        use std::vec;
+
+        let (subsys, file) = self.pressure_file(resource);
+        let file = io::File::open(self.get_path(subsys, Some(file)), "r").unwrap();
+        PressureMonitor {
+            name: resource.to_owned(),
+            file: file,
+            buf: vec::from_elem(256, 0u8)
+        }
+    }
+
+    /**
+     * Registers a PSI trigger that fires when the tasks of the group were
+     * stalled on `resource` for more than `stall_us` microseconds in any
+     * `window_us` microsecond window. `kind` is `some`, for any task stalled,
+     * or `full`, for all tasks stalled at once.
+     */
+    pub fn pressure_trigger(&self, resource: &str, kind: &str, stall_us: uint,
+                            window_us: uint) -> ErrorResult<PressureTrigger> {
+        let (subsys, file) = self.pressure_file(resource);
+        let file = match io::File::open(self.get_path(subsys, Some(file)), "r+") {
+            Ok(f) => f,
+            Err(e) => return Err(e)
+        };
+
+        // The kernel overwrites the last byte written with a NUL
+        match file.write_str(fmt!("%s %u %u\n", kind, stall_us, window_us)) {
+            Ok(_) => Ok(PressureTrigger { file: file }),
+            Err(e) => Err(e)
+        }
+    }
+
+    /// The controller and file holding the PSI of `resource`, one of `memory`,
+    /// `cpu` or `io`. Only cgroup2 has pressure files.
+    fn pressure_file(&self, resource: &str) -> (&'static str, &'static str) {
+        if self.hier != V2 {
+            sys::fail("Pressure stall information needs cgroup2");
+        }
+        match resource {
+            "memory" => ("memory", "memory.pressure"),
+            "cpu" => ("cpuacct", "cpu.pressure"),
+            "io" => ("blkio", "io.pressure"),
+            _ => sys::fail(fmt!("No pressure stall information for %s", resource))
+        }
+    }
+
+    pub fn stat_monitor(&self, subsys: &str, value: &str, keys: ~[~str]) -> StatMonitor {
+        use std::vec;
+
         let fname = self.get_path(subsys, Some(value));
         let file = io::File::open(fname, "r").unwrap();
-        Monitor { file: file }
+        let nkeys = keys.len();
+        StatMonitor {
+            name: value.to_owned(),
+            file: file,
+            buf: vec::from_elem(4096, 0u8),
+            keys: keys,
+            values: vec::from_elem(nkeys, None)
+        }
+    }
+
+    /**
+     * Lists the processes currently in the group. On v1 this reads the
+     * `tasks` file of the first controller, so threads are listed as well.
+     */
+    pub fn pids(&self) -> ~[pid_t] {
+        self.pids_in(self.subsys[0])
+    }
+
+    /// The processes in the group's directory of controller `subsys`
+    fn pids_in(&self, subsys: &str) -> ~[pid_t] {
+        use std::from_str::from_str;
+
+        let fname = self.get_path(subsys, Some("tasks"));
+        let contents = match io::read_file(fname) {
+            Ok(s) => s,
+            Err(e) => {
+                io::println(fmt!("Warning could not read %s (%s)", fname, e.msg()));
+                return ~[];
+            }
+        };
+
+        contents.line_iter().filter_map(|l| from_str::<pid_t>(l)).collect()
+    }
+
+    /// Sends `sig` to every process in the group, returns how many were
+    /// signalled.
+    pub fn signal_all(&self, sig: int) -> uint {
+        let mut count = 0;
+        for &pid in self.pids().iter() {
+            if sys::kill(pid, sig) == 0 {
+                count += 1;
+            }
+        }
+        count
+    }
+
+    /**
+     * Freezes or thaws every task in the group. Uses the `freezer` controller
+     * on v1, so does nothing there unless the group was created with it, and
+     * `cgroup.freeze` on v2. Returns whether the group could be frozen.
+     */
+    pub fn freeze(&self, frozen: bool) -> bool {
+        let (subsys, file, val, done) = match self.hier {
+            V1 => {
+                if !self.has_subsys("freezer") {
+                    return false;
+                }
+                if frozen {
+                    ("freezer", "freezer.state", "FROZEN\n", "FROZEN")
+                } else {
+                    ("freezer", "freezer.state", "THAWED\n", "THAWED")
+                }
+            }
+            V2 => {
+                let s : &str = self.subsys[0];
+                if frozen {
+                    (s, "cgroup.freeze", "1\n", "frozen 1")
+                } else {
+                    (s, "cgroup.freeze", "0\n", "frozen 0")
+                }
+            }
+        };
+
+        let err = self.set_str(subsys, file, val);
+        if err.is_err() {
+            io::println(fmt!("Warning could not write %s (%s)", file, err.msg()));
+            return false;
+        }
+
+        // Freezing is asynchronous, wait up to 100ms for it to finish
+        let state = if self.hier == V1 { "freezer.state" } else { "cgroup.events" };
+        for _ in range(0, 100) {
+            if self.get_str(subsys, state).find_str(done).is_some() {
+                return true;
+            }
+            sys::usleep(1000);
+        }
+
+        false
+    }
+
+    /**
+     * Kills every process left in the group and waits, for up to a second,
+     * for the group to become empty. The group is frozen while the processes
+     * are being killed so nothing can fork in the meantime. Returns the
+     * processes that were still in the group at the end.
+     */
+    pub fn kill_all(&self) -> ~[pid_t] {
+        let mut pids = self.pids();
+        if pids.len() == 0 {
+            return pids;
+        }
+
+        let frozen = self.freeze(true);
+        self.signal_all(sys::sig::KILL);
+        if frozen {
+            self.freeze(false);
+        }
+
+        for i in range(0, 1000) {
+            pids = self.pids();
+            if pids.len() == 0 {
+                break;
+            }
+            if i % 10 == 9 {
+                self.signal_all(sys::sig::KILL);
+            }
+            sys::usleep(1000);
+        }
+
+        pids
+    }
+
+    /**
+     * Reads the group's resource usage totals. Meant to be called once the
+     * tasks have exited, the values are read from `memory.max_usage_in_bytes`,
+     * `memory.failcnt`, `cpuacct.usage` and `cpuacct.stat` on v1, and from
+     * `memory.peak`, `memory.events` and `cpu.stat` on v2.
+     */
+    pub fn usage(&self) -> Usage {
+        let (nr_throttled, throttled_ns) = if self.has_subsys("cpu") {
+            match self.hier {
+                V1 => {
+                    let st = self.try_keyed("cpu", "cpu.stat",
+                                            ~[~"nr_throttled", ~"throttled_time"]);
+                    (st[0], st[1].map(|&t| t as u64))
+                }
+                V2 => {
+                    let st = self.try_keyed("cpu", "cpu.stat",
+                                            ~[~"nr_throttled", ~"throttled_usec"]);
+                    (st[0], st[1].map(|&t| t as u64 * 1000))
+                }
+            }
+        } else {
+            (None, None)
+        };
+
+        let (peak_tasks, pids_max_hits) = if self.has_subsys("pids") {
+            let events = self.try_keyed("pids", "pids.events", ~[~"max"]);
+            (if self.hier == V2 { self.try_int("pids", "pids.peak") } else { None }, events[0])
+        } else {
+            (None, None)
+        };
+
+        match self.hier {
+            V1 => {
+                let stat = self.try_keyed("cpuacct", "cpuacct.stat", ~[~"user", ~"system"]);
+                Usage {
+                    peak_mem: self.try_int("memory", "memory.max_usage_in_bytes"),
+                    failcnt: self.try_int("memory", "memory.failcnt"),
+                    cpu_ns: self.try_int("cpuacct", "cpuacct.usage").unwrap_or(0) as u64,
+                    user_ns: stat[0].unwrap_or(0) as u64 * NSEC_PER_TICK,
+                    sys_ns: stat[1].unwrap_or(0) as u64 * NSEC_PER_TICK,
+                    peak_tasks: peak_tasks,
+                    pids_max_hits: pids_max_hits,
+                    nr_throttled: nr_throttled,
+                    throttled_ns: throttled_ns
+                }
+            }
+            V2 => {
+                let events = self.try_keyed("memory", "memory.events", ~[~"max"]);
+                let stat = self.try_keyed("cpuacct", "cpu.stat",
+                                          ~[~"usage_usec", ~"user_usec", ~"system_usec"]);
+                Usage {
+                    peak_mem: self.try_int("memory", "memory.peak"),
+                    failcnt: events[0],
+                    cpu_ns: stat[0].unwrap_or(0) as u64 * 1000,
+                    user_ns: stat[1].unwrap_or(0) as u64 * 1000,
+                    sys_ns: stat[2].unwrap_or(0) as u64 * 1000,
+                    peak_tasks: peak_tasks,
+                    pids_max_hits: pids_max_hits,
+                    nr_throttled: nr_throttled,
+                    throttled_ns: throttled_ns
+                }
+            }
+        }
+    }
+
+    /// Like `get_int`, but returns `None` if the file can't be read
+    fn try_int(&self, subsys: &str, value: &str) -> Option<int> {
+        let fname = self.get_path(subsys, Some(value));
+        let mut buf = [0u8,..32];
+
+        match io::File::open(fname, "r") {
+            Ok(file) => match file.read_bytes(buf) {
+                Ok(len) if len > 0 => Some(atoi(buf, len as uint)),
+                _ => None
+            },
+            Err(_) => None
+        }
+    }
+
+    /// Reads `keys` from a `key value` file, all `None` if it can't be read
+    fn try_keyed(&self, subsys: &str, value: &str, keys: ~[~str]) -> ~[Option<int>] {
+        use std::vec;
+
+        let fname = self.get_path(subsys, Some(value));
+        match io::File::open(fname, "r") {
+            Ok(_) => {
+                let mut st = self.stat_monitor(subsys, value, keys);
+                st.sample();
+                st.values().to_owned()
+            }
+            Err(_) => vec::from_elem(keys.len(), None)
+        }
+    }
+
+    /**
+     * Limits the group's memory use to `mem` bytes, and memory plus swap use
+     * to `memsw` bytes. cgroup2 limits swap on its own, so the swap limit
+     * there is the difference of the two, which needs both to be given.
+     */
+    pub fn set_memory_limits(&self, mem: Option<u64>, memsw: Option<u64>) {
+        let set = |file: &str, val: u64| {
+            let err = self.set_str("memory", file, val.to_str() + "\n");
+            if err.is_err() {
+                sys::fail(fmt!("Could not set %s (%s)", file_name(self.hier, file), err.msg()));
+            }
+        };
+
+        // v1 requires memory.limit_in_bytes <= memory.memsw.limit_in_bytes
+        // at all times, so the memory limit has to be set first
+        match mem {
+            Some(m) => set("memory.limit_in_bytes", m),
+            None => ()
+        }
+        match (self.hier, mem, memsw) {
+            (_, _, None) => (),
+            (V1, _, Some(ms)) => set("memory.memsw.limit_in_bytes", ms),
+            (V2, Some(m), Some(ms)) => {
+                if ms < m {
+                    sys::fail("The memory+swap limit is below the memory limit");
+                }
+                set("memory.memsw.limit_in_bytes", ms - m)
+            }
+            (V2, None, Some(_)) => {
+                sys::fail("cgroup2 can only limit memory+swap together with a memory limit")
+            }
+        }
+    }
+
+    /// Limits the number of tasks, processes and threads, in the group to
+    /// `max`. Forks beyond the limit fail with EAGAIN.
+    pub fn set_max_pids(&self, max: uint) {
+        let err = self.set_str("pids", "pids.max", max.to_str() + "\n");
+        if err.is_err() {
+            sys::fail(fmt!("Could not set pids.max (%s)", err.msg()));
+        }
+    }
+
+    /**
+     * Limits the group to `cpus` CPUs worth of time, e.g. 0.5 or 2, in every
+     * 100ms period. Once a period's quota is used up the group is throttled
+     * until the next one.
+     */
+    pub fn set_cpu_quota(&self, cpus: f64) {
+        static PERIOD_US : int = 100_000;
+
+        let quota = (cpus * PERIOD_US as f64) as int;
+        let set = |file: &str, val: &str| {
+            let err = self.set_str("cpu", file, val + "\n");
+            if err.is_err() {
+                sys::fail(fmt!("Could not set %s (%s)", file, err.msg()));
+            }
+        };
+
+        match self.hier {
+            V1 => {
+                set("cpu.cfs_period_us", PERIOD_US.to_str());
+                set("cpu.cfs_quota_us", quota.to_str());
+            }
+            V2 => set("cpu.max", fmt!("%d %d", quota, PERIOD_US))
+        }
+    }
+
+    /**
+     * Restricts the group to the CPUs and memory nodes in the lists `cpus`
+     * and `mems`, e.g. `0-3,6`. A new v1 cpuset group starts out with neither
+     * and can't take tasks until both are set, so any list not given is
+     * copied from the parent group. On v2 a list not given is left empty,
+     * which means the parent's is used.
+     */
+    pub fn set_cpuset(&self, cpus: Option<&str>, mems: Option<&str>) {
+        let dir = self.get_path("cpuset", None);
+        let parent = match dir.rfind('/') {
+            Some(i) => dir.slice_to(i).to_owned(),
+            None => dir.clone()
+        };
+
+        for &(file, val) in [("cpuset.cpus", cpus), ("cpuset.mems", mems)].iter() {
+            let val = match (self.hier, val) {
+                (_, Some(v)) => Some(v.to_owned()),
+                (V1, None) => match io::read_file(parent + "/" + file) {
+                    Ok(v) => Some(v.trim().to_owned()),
+                    Err(e) => sys::fail(fmt!("Could not read parent %s (%s)", file, e.msg()))
+                },
+                (V2, None) => None
+            };
+
+            match val {
+                Some(val) => {
+                    let err = self.set_str("cpuset", file, val + "\n");
+                    if err.is_err() {
+                        sys::fail(fmt!("Could not set %s to %s (%s)", file, val, err.msg()));
+                    }
+                }
+                None => ()
+            }
+        }
+    }
+
+    /**
+     * Registers an eventfd for notifications about controller file `file`
+     * through `cgroup.event_control`, with `args` as the event arguments.
+     * Only v1 has `cgroup.event_control`.
+     */
+    pub fn register_event(&self, subsys: &str, file: &str, args: &str) -> ErrorResult<Event> {
+        let efd = match EventFd::new(EFD_NONBLOCK | EFD_CLOEXEC) {
+            Ok(e) => e,
+            Err(e) => return Err(e)
+        };
+        let target = match io::File::open(self.get_path(subsys, Some(file)), "r") {
+            Ok(f) => f,
+            Err(e) => return Err(e)
+        };
+
+        let mut line = fmt!("%d %d", efd.fd(), target.fd());
+        if args.len() > 0 {
+            line.push_char(' ');
+            line.push_str(args);
+        }
+        line.push_char('\n');
+
+        let err = self.set_str(subsys, "cgroup.event_control", line);
+        if err.is_err() {
+            return Err(err);
+        }
+
+        Ok(Event { efd: efd, target: target })
+    }
+
+    /**
+     * Registers a notification for the group's memory usage crossing
+     * `bytes`, in either direction. Only available on v1.
+     */
+    pub fn memory_threshold(&self, bytes: u64) -> ErrorResult<Event> {
+        self.register_event("memory", "memory.usage_in_bytes", bytes.to_str())
+    }
+
+    pub fn oom_watch(&self) -> OomWatch {
+        match self.hier {
+            V1 => match self.register_event("memory", "memory.oom_control", "") {
+                Ok(ev) => OomWatch { event: Some(ev), events: None, kills: 0 },
+                Err(e) => {
+                    io::println(fmt!("Warning could not watch for OOM kills (%s)", e.msg()));
+                    OomWatch { event: None, events: None, kills: 0 }
+                }
+            },
+            V2 => {
+                let mut st = self.stat_monitor("memory", "memory.events", ~[~"oom_kill"]);
+                st.sample();
+                let kills = st.values()[0].unwrap_or(0);
+                OomWatch { event: None, events: Some(st), kills: kills }
+            }
+        }
     }
 
     pub fn has_subsys(&self, sys: &str) -> bool {
         self.subsys.iter().any(|s| s.equiv(&sys))
     }
 
+    /**
+     * Opens the group's directory in the hierarchy perf events are scoped by,
+     * the `perf_event` controller's on v1. cgroup2 always has perf events.
+     */
+    pub fn perf_dir(&self) -> ErrorResult<io::File> {
+        let dir = match self.hier {
+            V1 => self.get_path("perf_event", None),
+            V2 => self.get_path(self.subsys[0], None)
+        };
+        io::File::open(dir, "r")
+    }
+
+    /// Whether this run made every one of the group's directories, rather
+    /// than reusing some left behind
+    pub fn created(&self) -> bool {
+        range(0, self.subsys.len()).all(|i| !self.owns_dir(i) || self.made[i])
+    }
+
+    /**
+     * Whether the directory of the `i`th controller is created and removed
+     * along with the group. On v2 only the first one is, on v1 all but those
+     * mounted in the same hierarchy as an earlier one.
+     */
+    fn owns_dir(&self, i: uint) -> bool {
+        match self.hier {
+            V2 => i == 0,
+            V1 => {
+                let id = hierarchy_id(self.subsys[i]);
+                id.is_none() || !self.subsys.slice_to(i).iter().any(|s| hierarchy_id(*s) == id)
+            }
+        }
+    }
+
+    /// Whether `file` exists in this hierarchy, files without a cgroup2
+    /// equivalent are silently skipped by the setters.
+    pub fn has_file(&self, file: &str) -> bool {
+        self.hier == V1 || file_name(V2, file).len() > 0
+    }
+
+    /**
+     * Enables the group's controllers for the children of the root cgroup,
+     * cgroup2 only exposes a controller's files in a group once its parent
+     * has it in `cgroup.subtree_control`.
+     */
+    fn enable_controllers(&self) {
+        let fname = CGPATH + "cgroup.subtree_control";
+        let file = io::File::open(fname, "w").unwrap();
+
+        for s in self.subsys.iter() {
+            let ctrl = controller_name(V2, *s);
+            let ret = file.write_str("+" + ctrl + "\n");
+            match ret {
+                Err(e) => io::println(fmt!("Warning could not enable controller %s (%s)",
+                                           ctrl, e.msg())),
+                _ => ()
+            }
+        }
+    }
+
     fn get_path(&self, subsys: &str, file: Option<&str>) -> ~str {
         if !self.has_subsys(subsys) {
             sys::fail(fmt!("Group does not have subsys %s", subsys));
         }
 
-        let mut path = CGPATH + subsys + "/" + self.name;
+        let mut path = match self.hier {
+            V1 => CGPATH + subsys + "/" + self.name,
+            V2 => CGPATH + self.name
+        };
         match file {
             Some(f) => {
+                let f = file_name(self.hier, f);
+                if f.len() == 0 {
+                    sys::fail(fmt!("No cgroup2 equivalent for %s", file.unwrap()));
+                }
                 path.push_char('/');
                 path.push_str(f);
             }
@@ -112,27 +883,181 @@ impl Group {
     }
 }
 
+/**
+ * Generates a group name from `prefix`, the pid and the current time, so
+ * concurrent runs on the same machine don't pick the same name.
+ */
+pub fn unique_name(prefix: &str) -> ~str {
+    fmt!("%s-%d-%x", prefix, sys::getpid() as int, sys::getclock() as uint)
+}
+
+/**
+ * Takes the lock file for group `name`. The lock is released when the
+ * returned file is closed, so it is never left held by a run that crashed.
+ * Lock files are never removed: a run that opened the file just before it was
+ * unlinked could lock the old file while another run locks a new one.
+ */
+fn lock_name(name: &str) -> ErrorResult<io::File> {
+    let err = io::mkdir(LOCKDIR, 448); // 0700
+    if err.is_err() && !err.is(sys::err::EEXIST) {
+        return Err(err);
+    }
+
+    let file = match io::File::open(LOCKDIR + name + ".lock", "a") {
+        Ok(f) => f,
+        Err(e) => return Err(e)
+    };
+
+    let err = file.try_lock();
+    if err.is(sys::err::EAGAIN) {
+        return Err(sys::err::Error::errno(sys::err::EBUSY));
+    } else if err.is_err() {
+        return Err(err);
+    }
+
+    Ok(file)
+}
+
+/**
+ * Parses a cpuset style list of numbers and ranges, such as `0-3,6`, into the
+ * numbers it contains. `None` if the list is malformed.
+ */
+pub fn parse_list(list: &str) -> Option<~[uint]> {
+    use std::from_str::from_str;
+
+    let mut nums = ~[];
+
+    for part in list.split_iter(',') {
+        let ends : ~[&str] = part.splitn_iter('-', 1).collect();
+        let lo = match from_str::<uint>(ends[0]) {
+            Some(n) => n,
+            None => return None
+        };
+        let hi = if ends.len() == 2 {
+            match from_str::<uint>(ends[1]) {
+                Some(n) if n >= lo => n,
+                _ => return None
+            }
+        } else {
+            lo
+        };
+
+        for n in range(lo, hi + 1) {
+            nums.push(n);
+        }
+    }
+
+    Some(nums)
+}
+
+/**
+ * Works out which hierarchy is mounted. A cgroup2 mount has a
+ * `cgroup.controllers` file at its root, v1 mounts have a directory per
+ * controller instead.
+ */
+pub fn detect() -> Hierarchy {
+    match io::File::open(CGPATH + "cgroup.controllers", "r") {
+        Ok(_) => V2,
+        Err(_) => V1
+    }
+}
+
+/// The id of the v1 hierarchy controller `subsys` is mounted in, from
+/// `/proc/cgroups`. Controllers mounted together share an id.
+fn hierarchy_id(subsys: &str) -> Option<int> {
+    use std::from_str::from_str;
+
+    match io::read_file("/proc/cgroups") {
+        Ok(s) => {
+            for line in s.line_iter() {
+                let fields : ~[&str] = line.word_iter().collect();
+                if fields.len() >= 2 && fields[0] == subsys {
+                    return from_str::<int>(fields[1]);
+                }
+            }
+            None
+        }
+        Err(_) => None
+    }
+}
+
+/// Whether controller `subsys` can be used in the mounted hierarchy
+pub fn available(subsys: &str) -> bool {
+    match detect() {
+        V1 => match io::File::open(CGPATH + subsys + "/tasks", "r") {
+            Ok(_) => true,
+            Err(_) => false
+        },
+        V2 => match io::read_file(CGPATH + "cgroup.controllers") {
+            Ok(s) => s.word_iter().any(|c| c == controller_name(V2, subsys)),
+            Err(_) => false
+        }
+    }
+}
+
+/// The name of controller file `file` in hierarchy `hier`, the empty string
+/// if there is none.
+pub fn file_name<'a>(hier: Hierarchy, file: &'a str) -> &'a str {
+    if hier == V1 {
+        return file;
+    }
+    for &(v1, v2) in V2_FILES.iter() {
+        if v1 == file {
+            return v2;
+        }
+    }
+    file
+}
+
+/// The name of controller `subsys` in hierarchy `hier`, cgroup2 merged
+/// `cpuacct` into `cpu` and renamed `blkio` to `io`.
+pub fn controller_name<'a>(hier: Hierarchy, subsys: &'a str) -> &'a str {
+    match (hier, subsys) {
+        (V2, "cpuacct") => "cpu",
+        (V2, "blkio") => "io",
+        _ => subsys
+    }
+}
+
 impl Drop for Group {
     fn drop(&self) {
+        if self.subsys.len() == 0 {
+            return;
+        }
 
-        for s in self.subsys.iter() {
-            let fname = CGPATH + *s + "/tasks";
-            let file = io::File::open(fname, "w").unwrap();
-            file.write_str("0\n");
-            file.close();
+        // A reused group was empty when the run started, but other processes
+        // may have been added to it since, so it is left as it is
+        if !self.created() {
+            return;
         }
 
-        for s in self.subsys.iter() {
-            let dirname = self.get_path(*s, None);
-            let err = io::rmdir(dirname);
-            if err.is_err() {
-                io::println(fmt!("Warning trying to remove %s (%s)", dirname, err.msg()));
+        let left = self.kill_all();
+        if left.len() > 0 {
+            let pids : ~[~str] = left.iter().map(|p| p.to_str()).collect();
+            io::println(fmt!("Warning %u processes left in group %s (%s)",
+                             left.len(), self.name, pids.connect(" ")));
+        }
+
+        // grease-bench itself is never added to the group, so there is
+        // nothing to move out before removing it
+        for (i, s) in self.subsys.iter().enumerate() {
+            if self.owns_dir(i) {
+                let dirname = self.get_path(*s, None);
+                let err = io::rmdir(dirname);
+                if err.is_err() {
+                    io::println(fmt!("Warning trying to remove %s (%s)", dirname, err.msg()));
+                }
             }
         }
     }
 }
 
 impl Monitor {
+    /// The controller file being monitored, as named by the caller
+    pub fn name<'a>(&'a self) -> &'a str {
+        self.name.as_slice()
+    }
+
     pub fn get_int(&self) -> int {
         let mut buf = [0u8,..32];
 
@@ -141,6 +1066,31 @@ impl Monitor {
         atoi(buf, len)
     }
 
+    /**
+     * Reads a file holding a list of whitespace separated integers, such as
+     * `cpuacct.usage_percpu`, into `out`. `buf` is grown to fit the whole
+     * file and can be reused across calls.
+     */
+    pub fn get_ints(&self, buf: &mut ~[u8], out: &mut ~[int]) {
+        let len = read_all(&self.file, buf);
+        let buf : &[u8] = *buf;
+
+        out.clear();
+        let mut i = 0;
+        while i < len {
+            while i < len && (buf[i] == ' ' as u8 || buf[i] == '\n' as u8) {
+                i += 1;
+            }
+            let start = i;
+            while i < len && buf[i] != ' ' as u8 && buf[i] != '\n' as u8 {
+                i += 1;
+            }
+            if i > start {
+                out.push(atoi(buf.slice(start, i), i - start));
+            }
+        }
+    }
+
     pub fn get_str(&self) -> ~str {
         use std::{str,cast};
 
@@ -155,6 +1105,283 @@ impl Monitor {
     }
 }
 
+impl Event {
+    /// How many times the event fired since the last call, without blocking
+    pub fn fired(&self) -> u64 {
+        match self.efd.read() {
+            Ok(n) => n,
+            Err(_) => 0
+        }
+    }
+
+    /// The eventfd, for waiting on the event with poll or epoll
+    pub fn fd(&self) -> int {
+        self.efd.fd()
+    }
+}
+
+impl OomWatch {
+    /// Whether an OOM kill happened since the last call
+    pub fn check(&mut self) -> bool {
+        match self.event {
+            Some(ref ev) => return ev.fired() > 0,
+            None => ()
+        }
+        match self.events {
+            Some(ref mut st) => {
+                st.sample();
+                let kills = st.values()[0].unwrap_or(0);
+                let killed = kills > self.kills;
+                self.kills = kills;
+                killed
+            }
+            None => false
+        }
+    }
+}
+
+impl StatMonitor {
+    pub fn name<'a>(&'a self) -> &'a str {
+        self.name.as_slice()
+    }
+
+    pub fn keys<'a>(&'a self) -> &'a [~str] {
+        self.keys.as_slice()
+    }
+
+    /// The values read by the last call to `sample`, in the same order as
+    /// `keys`. Keys missing from the file are `None`.
+    pub fn values<'a>(&'a self) -> &'a [Option<int>] {
+        self.values.as_slice()
+    }
+
+    /**
+     * Reads the whole file and updates the values of the selected keys. The
+     * buffer is only grown if the file does not fit in it, which for the
+     * stat files happens at most once.
+     */
+    pub fn sample(&mut self) {
+        let len = read_all(&self.file, &mut self.buf);
+        parse_keyed(self.buf.slice_to(len), self.keys, self.values);
+    }
+}
+
+impl PressureMonitor {
+    /// The resource being monitored
+    pub fn name<'a>(&'a self) -> &'a str {
+        self.name.as_slice()
+    }
+
+    pub fn sample(&mut self) -> Pressure {
+        let len = read_all(&self.file, &mut self.buf);
+        match parse_pressure(self.buf.slice_to(len)) {
+            Some(p) => p,
+            None => sys::fail(fmt!("Could not parse %s.pressure", self.name))
+        }
+    }
+}
+
+impl PressureTrigger {
+    pub fn fd(&self) -> int {
+        self.file.fd()
+    }
+}
+
+impl IoMonitor {
+    pub fn columns() -> ~[~str] {
+        ~[~"io.read_bytes", ~"io.write_bytes", ~"io.read_ops", ~"io.write_ops"]
+    }
+
+    pub fn sample(&mut self) -> IoStats {
+        let mut st = IoStats { read_bytes: 0, write_bytes: 0, read_ops: 0, write_ops: 0 };
+
+        let len = read_all(&self.bytes, &mut self.buf);
+        match self.hier {
+            V1 => {
+                let (r, w) = parse_blkio(self.buf.slice_to(len));
+                st.read_bytes = r;
+                st.write_bytes = w;
+            }
+            V2 => parse_io_stat(self.buf.slice_to(len), &mut st)
+        }
+
+        match self.ops {
+            Some(ref f) => {
+                let len = read_all(f, &mut self.buf);
+                let (r, w) = parse_blkio(self.buf.slice_to(len));
+                st.read_ops = r;
+                st.write_ops = w;
+            }
+            None => ()
+        }
+
+        st
+    }
+}
+
+/**
+ * Reads the whole of `file` into `buf` from the start, growing `buf` if the
+ * file does not fit, and returns the number of bytes read.
+ */
+fn read_all(file: &io::File, buf: &mut ~[u8]) -> uint {
+    use std::vec;
+
+    let mut len = 0;
+    loop {
+        if len == buf.len() {
+            let mut bigger = vec::from_elem(buf.len() * 2, 0u8);
+            for (i, &b) in buf.iter().enumerate() {
+                bigger[i] = b;
+            }
+            *buf = bigger;
+        }
+
+        let n = file.read_bytes(buf.mut_slice_from(len)).unwrap() as uint;
+        if n == 0 {
+            break;
+        }
+        len += n;
+    }
+    file.reset();
+
+    len
+}
+
+/**
+ * Parses a PSI file of the form
+ *
+ *     some avg10=0.12 avg60=0.05 avg300=0.01 total=123456
+ *     full avg10=0.00 avg60=0.00 avg300=0.00 total=4567
+ *
+ * `None` if there is no `some` line.
+ */
+pub fn parse_pressure(buf: &[u8]) -> Option<Pressure> {
+    use std::from_str::from_str;
+    use std::str;
+
+    let mut some = None;
+    let mut full = None;
+
+    for line in str::from_bytes_slice(buf).line_iter() {
+        let mut words = line.word_iter();
+        let kind = words.next().unwrap_or("");
+
+        let mut psi = PsiLine { avg10: 0.0, avg60: 0.0, avg300: 0.0, total_us: 0 };
+        for w in words {
+            let kv : ~[&str] = w.splitn_iter('=', 1).collect();
+            if kv.len() == 2 {
+                let avg = from_str::<f64>(kv[1]).unwrap_or(0.0);
+                match kv[0] {
+                    "avg10" => psi.avg10 = avg,
+                    "avg60" => psi.avg60 = avg,
+                    "avg300" => psi.avg300 = avg,
+                    "total" => psi.total_us = from_str::<u64>(kv[1]).unwrap_or(0),
+                    _ => ()
+                }
+            }
+        }
+
+        match kind {
+            "some" => some = Some(psi),
+            "full" => full = Some(psi),
+            _ => ()
+        }
+    }
+
+    some.map(|&s| Pressure { some: s, full: full })
+}
+
+/**
+ * Parses a v1 blkio file of `MAJ:MIN Op value` lines, returning the `Read`
+ * and `Write` values summed over all devices. The `Total` line at the end has
+ * no device and is skipped.
+ */
+pub fn parse_blkio(buf: &[u8]) -> (u64, u64) {
+    let mut read = 0u64;
+    let mut write = 0u64;
+
+    for line in buf.split_iter(|&b| b == '\n' as u8) {
+        let fields : ~[&[u8]] = line.split_iter(|&b| b == ' ' as u8)
+                                    .filter(|f| f.len() > 0).collect();
+        if fields.len() == 3 {
+            let v = atoi(fields[2], fields[2].len()) as u64;
+            if fields[1] == bytes!("Read") {
+                read += v;
+            } else if fields[1] == bytes!("Write") {
+                write += v;
+            }
+        }
+    }
+
+    (read, write)
+}
+
+/**
+ * Parses a v2 `io.stat` file, one line per device of `MAJ:MIN key=value ...`,
+ * summing the `rbytes`, `wbytes`, `rios` and `wios` of all devices into `st`.
+ */
+pub fn parse_io_stat(buf: &[u8], st: &mut IoStats) {
+    for line in buf.split_iter(|&b| b == '\n' as u8) {
+        for field in line.split_iter(|&b| b == ' ' as u8) {
+            let mut eq = 0;
+            while eq < field.len() && field[eq] != '=' as u8 {
+                eq += 1;
+            }
+            if eq + 1 < field.len() {
+                let key = field.slice_to(eq);
+                let val = field.slice_from(eq + 1);
+                let v = atoi(val, val.len()) as u64;
+                if key == bytes!("rbytes") {
+                    st.read_bytes += v;
+                } else if key == bytes!("wbytes") {
+                    st.write_bytes += v;
+                } else if key == bytes!("rios") {
+                    st.read_ops += v;
+                } else if key == bytes!("wios") {
+                    st.write_ops += v;
+                }
+            }
+        }
+    }
+}
+
+/**
+ * Parses `key value` lines from `buf`, storing the value of each of `keys`
+ * found in the matching slot of `values`. Slots for keys that aren't found
+ * are set to `None`.
+ */
+pub fn parse_keyed(buf: &[u8], keys: &[~str], values: &mut [Option<int>]) {
+    for v in values.mut_iter() {
+        *v = None;
+    }
+
+    let mut start = 0;
+    while start < buf.len() {
+        let mut end = start;
+        while end < buf.len() && buf[end] != '\n' as u8 {
+            end += 1;
+        }
+
+        let line = buf.slice(start, end);
+        let mut sp = 0;
+        while sp < line.len() && line[sp] != ' ' as u8 {
+            sp += 1;
+        }
+
+        if sp + 1 < line.len() {
+            let key = line.slice_to(sp);
+            let val = line.slice_from(sp + 1);
+            for (i, k) in keys.iter().enumerate() {
+                if k.as_bytes() == key {
+                    values[i] = Some(atoi(val, val.len()));
+                }
+            }
+        }
+
+        start = end + 1;
+    }
+}
+
 fn atoi(buf: &[u8], len: uint) -> int {
     let mut accum = 0;
     let mut i = 0;
@@ -178,3 +1405,53 @@ fn atoi(buf: &[u8], len: uint) -> int {
 
     return neg * accum;
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_keyed, parse_blkio, parse_io_stat, parse_pressure, IoStats};
+
+    #[test]
+    fn keyed() {
+        let buf = bytes!("cache 8192\nrss 4096\npgmajfault 3\n");
+        let keys = ~[~"rss", ~"pgmajfault", ~"swap"];
+        let mut values = ~[Some(1), Some(2), Some(3)];
+        parse_keyed(buf, keys, values);
+        assert_eq!(values, ~[Some(4096), Some(3), None]);
+    }
+
+    #[test]
+    fn blkio() {
+        let buf = bytes!("8:0 Read 1024\n8:0 Write 512\n8:0 Sync 1536\n8:0 Async 0\n\
+                          8:0 Total 1536\n8:16 Read 100\n8:16 Write 10\nTotal 1646\n");
+        assert_eq!(parse_blkio(buf), (1124, 522));
+    }
+
+    #[test]
+    fn io_stat() {
+        let buf = bytes!("8:0 rbytes=1024 wbytes=512 rios=4 wios=2 dbytes=0 dios=0\n\
+                          8:16 rbytes=100 wbytes=10 rios=1 wios=1 dbytes=0 dios=0\n");
+        let mut st = IoStats { read_bytes: 0, write_bytes: 0, read_ops: 0, write_ops: 0 };
+        parse_io_stat(buf, &mut st);
+        assert_eq!((st.read_bytes, st.write_bytes, st.read_ops, st.write_ops),
+                   (1124, 522, 5, 3));
+    }
+
+    #[test]
+    fn pressure() {
+        let buf = bytes!("some avg10=0.12 avg60=0.05 avg300=0.01 total=123456\n\
+                          full avg10=0.00 avg60=0.00 avg300=0.00 total=4567\n");
+        let p = parse_pressure(buf).unwrap();
+        assert_eq!(p.some.avg10, 0.12);
+        assert_eq!(p.some.avg60, 0.05);
+        assert_eq!(p.some.total_us, 123456);
+        assert_eq!(p.full.unwrap().total_us, 4567);
+
+        // cpu.pressure has no full line on older kernels
+        let buf = bytes!("some avg10=1.00 avg60=0.50 avg300=0.10 total=42\n");
+        let p = parse_pressure(buf).unwrap();
+        assert_eq!(p.some.total_us, 42);
+        assert!(p.full.is_none());
+
+        assert!(parse_pressure(bytes!("")).is_none());
+    }
+}
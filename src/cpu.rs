@@ -0,0 +1,115 @@
+use cgroup;
+
+use std::vec;
+
+/**
+ * Samples the group's CPU time and turns it into utilisation over the time
+ * between samples, in percent of one CPU. On v1 the time is read from
+ * `cpuacct.usage`, with a series per CPU from `cpuacct.usage_percpu`. v2 only
+ * has the total, the `usage_usec` key of `cpu.stat`.
+ */
+pub struct CpuSampler {
+    priv usage: Option<cgroup::Monitor>,
+    priv stat: Option<cgroup::StatMonitor>,
+    priv percpu: Option<cgroup::Monitor>,
+    priv buf: ~[u8],
+    priv vals: ~[int],
+    priv prev_time: u64,
+    priv prev_total: u64,
+    priv prev_percpu: ~[int]
+}
+
+impl CpuSampler {
+    pub fn new(group: &cgroup::Group) -> CpuSampler {
+        let mut s = match group.hierarchy() {
+            cgroup::V1 => CpuSampler {
+                usage: Some(group.monitor("cpuacct", "cpuacct.usage")),
+                stat: None,
+                percpu: Some(group.monitor("cpuacct", "cpuacct.usage_percpu")),
+                buf: vec::from_elem(4096, 0u8),
+                vals: ~[],
+                prev_time: 0,
+                prev_total: 0,
+                prev_percpu: ~[]
+            },
+            cgroup::V2 => CpuSampler {
+                usage: None,
+                stat: Some(group.stat_monitor("cpuacct", "cpu.stat", ~[~"usage_usec"])),
+                percpu: None,
+                buf: ~[],
+                vals: ~[],
+                prev_time: 0,
+                prev_total: 0,
+                prev_percpu: ~[]
+            }
+        };
+
+        // Read the per CPU values once to find out how many CPUs there are
+        match s.percpu {
+            Some(ref m) => {
+                m.get_ints(&mut s.buf, &mut s.vals);
+                s.prev_percpu = vec::from_elem(s.vals.len(), 0);
+            }
+            None => ()
+        }
+
+        s
+    }
+
+    pub fn columns(&self) -> ~[~str] {
+        let mut cols = ~[~"cpu.usage_ns", ~"cpu.util_pct"];
+        for i in range(0, self.prev_percpu.len()) {
+            cols.push(fmt!("cpu%u.util_pct", i));
+        }
+        cols
+    }
+
+    /**
+     * Appends the total CPU time, the total utilisation and the utilisation
+     * of each CPU to `row`. The utilisation is over the time since the last
+     * call, `now` being the current `sys::getclock` time, so it is missing
+     * from the first sample.
+     */
+    pub fn sample(&mut self, now: u64, row: &mut ~[Option<int>]) {
+        let total = match self.usage {
+            Some(ref m) => m.get_int() as u64,
+            None => match self.stat {
+                Some(ref mut st) => {
+                    st.sample();
+                    st.values()[0].unwrap_or(0) as u64 * 1000
+                }
+                None => 0
+            }
+        };
+
+        let elapsed = now - self.prev_time;
+        let first = self.prev_time == 0 || elapsed == 0;
+
+        row.push(Some(total as int));
+        row.push(if first { None } else { Some(util(total - self.prev_total, elapsed)) });
+
+        match self.percpu {
+            Some(ref m) => {
+                m.get_ints(&mut self.buf, &mut self.vals);
+                for i in range(0, self.prev_percpu.len()) {
+                    let v = if i < self.vals.len() { self.vals[i] } else { 0 };
+                    row.push(if first {
+                        None
+                    } else {
+                        Some(util((v - self.prev_percpu[i]) as u64, elapsed))
+                    });
+                    self.prev_percpu[i] = v;
+                }
+            }
+            None => ()
+        }
+
+        self.prev_total = total;
+        self.prev_time = now;
+    }
+}
+
+/// Rounded percentage of `elapsed` that `used` makes up
+fn util(used: u64, elapsed: u64) -> int {
+    ((used * 100 + elapsed / 2) / elapsed) as int
+}
@@ -0,0 +1,399 @@
+use sys;
+use sys::io;
+use cgroup;
+use perf;
+use std::from_str::from_str;
+
+/// The longest --group-name, so paths to the group's files and its lock file
+/// stay well under the 260 bytes the raw path helpers take
+static MAX_GROUP_NAME : uint = 128;
+
+static USAGE : &'static str = "Usage: grease-bench [options] [--] command [args...]
+       grease-bench convert [--format FORMAT] TRACE OUTPUT
+
+Options:
+    -o, --output FILE       Write samples to FILE (default: mem.csv)
+    -f, --format FORMAT     Output format, csv, json or binary (default: json if FILE
+                            ends in .json, binary if it ends in .gbt, csv otherwise)
+    -u, --user USER         Run the command as USER, a name or uid (default: 1000)
+    -g, --group GROUP       Run the command with primary group GROUP, a name or gid
+                            (default: the primary group of USER)
+    -i, --interval USECS    Take a sample every USECS microseconds, 0 samples as fast
+                            as possible (default: 1000)
+    -n, --group-name NAME   Name of the cgroup to create, a cgroup left behind by an
+                            earlier run with this name is reused (default: a unique
+                            name generated from the pid and time)
+    -x, --exclusive         Fail if the cgroup given by --group-name already exists
+    -m, --monitor SUBSYS:FILE
+                            Also log the value of cgroup file FILE of controller
+                            SUBSYS, e.g. memory:memory.max_usage_in_bytes. May be
+                            given more than once, one column per monitor
+    -c, --cpu               Log CPU time and utilisation, in total and per CPU on
+                            cgroup v1
+        --io                Log bytes and operations read and written to block
+                            devices
+    -s, --stat SUBSYS:FILE:KEY[,KEY...]
+                            Log the values of KEYs from the `key value` file FILE,
+                            e.g. memory:memory.stat:rss,cache,pgmajfault
+        --procs             Log the RSS, PSS, swap and anonymous memory of the
+                            command's processes from /proc, broken down by command
+                            name on every sample and at the peak
+        --perf EVENT[,EVENT...]
+                            Count perf events and log the counts: instructions,
+                            cycles, cache-misses, branch-misses, task-clock,
+                            page-faults, context-switches, or all of them
+        --perf-cgroup       Count the perf events of everything in the cgroup rather
+                            than of the command and the processes it starts
+    -P, --pressure RES[,RES...]
+                            Log the total time tasks were stalled on each resource
+                            RES, one of memory, cpu and io (cgroup v2 only)
+        --stall-trigger RES:KIND:STALL_MS:WINDOW_MS
+                            Log a note whenever some or all (KIND some or full)
+                            tasks were stalled on RES for STALL_MS milliseconds
+                            within a WINDOW_MS window, 500 to 10000 milliseconds
+                            (cgroup v2 only)
+    -t, --timeout SECS      Terminate the command if it runs longer than SECS seconds,
+                            0 means no timeout (default: 0)
+        --kill-grace SECS   Seconds to wait after SIGTERM before sending SIGKILL to the
+                            remaining tasks (default: 5)
+    -l, --memory-limit SIZE Limit the command's memory use to SIZE bytes, K, M and G
+                            suffixes are accepted
+        --memsw-limit SIZE  Limit the command's memory plus swap use to SIZE bytes,
+                            needs --memory-limit
+        --cpus LIST         Run the command on the CPUs in LIST only, e.g. 0-3,6, in a
+                            cpuset cgroup and with its CPU affinity set
+        --mems LIST         Allocate the command's memory from the NUMA nodes in LIST
+                            only
+        --cpu-quota CPUS    Limit the command to CPUS CPUs worth of time, e.g. 0.5 or
+                            2, at least 0.01, and log how often it was throttled
+        --max-pids N        Limit the number of processes and threads the command may
+                            run at once to N
+    -T, --threshold SIZE    Log the exact time memory usage crosses SIZE bytes, may be
+                            given more than once (cgroup v1 only)
+    -r, --runs N            Run the command N times, each in a fresh cgroup and with its
+                            own trace file, and print statistics across the runs
+                            (default: 1)
+    -w, --warmup N          Run the command N times before the measured runs without
+                            keeping their traces (default: 0)
+    -h, --help              Print this message and exit
+";
+
+pub struct Options {
+    output: ~str,
+    format: Option<~str>,
+    user: ~str,
+    group: Option<~str>,
+    interval: uint,
+    group_name: Option<~str>,
+    exclusive: bool,
+    monitors: ~[(~str, ~str)],
+    stats: ~[(~str, ~str, ~[~str])],
+    cpu: bool,
+    io: bool,
+    pressure: ~[~str],
+    procs: bool,
+    perf: ~[~str],
+    perf_cgroup: bool,
+    stall_triggers: ~[(~str, ~str, uint, uint)],
+    max_pids: Option<uint>,
+    cpus: Option<~str>,
+    cpu_quota: Option<f64>,
+    mems: Option<~str>,
+    timeout: uint,
+    memory_limit: Option<u64>,
+    memsw_limit: Option<u64>,
+    thresholds: ~[u64],
+    runs: uint,
+    warmup: uint,
+    kill_grace: uint,
+    command: ~[~str]
+}
+
+impl Options {
+    pub fn default() -> Options {
+        Options {
+            output: ~"mem.csv",
+            format: None,
+            user: ~"1000",
+            group: None,
+            interval: 1000,
+            group_name: None,
+            exclusive: false,
+            monitors: ~[],
+            stats: ~[],
+            cpu: false,
+            io: false,
+            pressure: ~[],
+            procs: false,
+            perf: ~[],
+            perf_cgroup: false,
+            stall_triggers: ~[],
+            max_pids: None,
+            cpus: None,
+            cpu_quota: None,
+            mems: None,
+            timeout: 0,
+            memory_limit: None,
+            memsw_limit: None,
+            thresholds: ~[],
+            runs: 1,
+            warmup: 0,
+            kill_grace: 5,
+            command: ~[]
+        }
+    }
+}
+
+/**
+ * Parses the arguments following the program name. Option parsing stops at
+ * the first non-option argument or at `--`, everything after that is the
+ * command to run.
+ */
+pub fn parse(args: &[~str]) -> Options {
+    let mut opts = Options::default();
+    let mut i = 0;
+
+    while i < args.len() {
+        let arg : &str = args[i];
+
+        if arg == "--" {
+            i += 1;
+            break;
+        }
+        if !arg.starts_with("-") || arg == "-" {
+            break;
+        }
+
+        let (name, inline) = match arg.find('=') {
+            Some(idx) if arg.starts_with("--") => (arg.slice_to(idx), Some(arg.slice_from(idx+1))),
+            _ => (arg, None)
+        };
+
+        match name {
+            "-h" | "--help" => {
+                io::print(USAGE);
+                sys::exit(0);
+            }
+            "-o" | "--output" => {
+                opts.output = value(args, &mut i, name, inline).to_owned();
+            }
+            "-f" | "--format" => {
+                let v = value(args, &mut i, name, inline);
+                if v != "csv" && v != "json" && v != "binary" {
+                    usage_error(fmt!("Unknown output format '%s'", v));
+                }
+                opts.format = Some(v.to_owned());
+            }
+            "-u" | "--user" | "--uid" => {
+                opts.user = value(args, &mut i, name, inline).to_owned();
+            }
+            "-g" | "--group" | "--gid" => {
+                opts.group = Some(value(args, &mut i, name, inline).to_owned());
+            }
+            "-i" | "--interval" => {
+                opts.interval = parse_int(name, value(args, &mut i, name, inline)) as uint;
+            }
+            "-n" | "--group-name" => {
+                let v = value(args, &mut i, name, inline);
+                if v.len() == 0 || v.find('/').is_some() || v == "." || v == ".." {
+                    usage_error(fmt!("Invalid group name '%s'", v));
+                }
+                if v.len() > MAX_GROUP_NAME {
+                    usage_error(fmt!("Group name is longer than %u characters", MAX_GROUP_NAME));
+                }
+                opts.group_name = Some(v.to_owned());
+            }
+            "-x" | "--exclusive" => {
+                opts.exclusive = true;
+            }
+            "-m" | "--monitor" => {
+                let v = value(args, &mut i, name, inline);
+                let parts : ~[&str] = v.split_iter(':').collect();
+                if parts.len() != 2 || parts[0].len() == 0 || parts[1].len() == 0 {
+                    usage_error(fmt!("Invalid monitor '%s', expected SUBSYS:FILE", v));
+                }
+                opts.monitors.push((parts[0].to_owned(), parts[1].to_owned()));
+            }
+            "-c" | "--cpu" => {
+                opts.cpu = true;
+            }
+            "--io" => {
+                opts.io = true;
+            }
+            "-s" | "--stat" => {
+                let v = value(args, &mut i, name, inline);
+                let parts : ~[&str] = v.split_iter(':').collect();
+                if parts.len() != 3 || parts.iter().any(|p| p.len() == 0) {
+                    usage_error(fmt!("Invalid stat '%s', expected SUBSYS:FILE:KEY[,KEY...]", v));
+                }
+                let keys = parts[2].split_iter(',').filter(|k| k.len() > 0)
+                                   .map(|k| k.to_owned()).collect();
+                opts.stats.push((parts[0].to_owned(), parts[1].to_owned(), keys));
+            }
+            "--procs" => {
+                opts.procs = true;
+            }
+            "--perf" => {
+                let v = value(args, &mut i, name, inline);
+                for e in v.split_iter(',') {
+                    if e == "all" {
+                        opts.perf = perf::all_events();
+                    } else if perf::is_event(e) {
+                        opts.perf.push(e.to_owned());
+                    } else {
+                        usage_error(fmt!("Unknown perf event '%s'", e));
+                    }
+                }
+            }
+            "--perf-cgroup" => {
+                opts.perf_cgroup = true;
+            }
+            "-P" | "--pressure" => {
+                let v = value(args, &mut i, name, inline);
+                for r in v.split_iter(',') {
+                    if !is_psi_resource(r) {
+                        usage_error(fmt!("Unknown pressure resource '%s'", r));
+                    }
+                    opts.pressure.push(r.to_owned());
+                }
+            }
+            "--stall-trigger" => {
+                let v = value(args, &mut i, name, inline);
+                let parts : ~[&str] = v.split_iter(':').collect();
+                if parts.len() != 4 || !is_psi_resource(parts[0])
+                        || (parts[1] != "some" && parts[1] != "full") {
+                    usage_error(fmt!("Invalid stall trigger '%s', expected \
+                                      RES:some|full:STALL_MS:WINDOW_MS", v));
+                }
+                let stall = parse_int(name, parts[2]) as uint;
+                let window = parse_int(name, parts[3]) as uint;
+                if window < 500 || window > 10000 || stall == 0 || stall > window {
+                    usage_error(fmt!("Invalid stall trigger '%s', the window must be 500 to \
+                                      10000 milliseconds and at least the stall time", v));
+                }
+                opts.stall_triggers.push((parts[0].to_owned(), parts[1].to_owned(),
+                                          stall, window));
+            }
+            "-t" | "--timeout" => {
+                opts.timeout = parse_int(name, value(args, &mut i, name, inline)) as uint;
+            }
+            "-l" | "--memory-limit" => {
+                opts.memory_limit = Some(parse_size(name, value(args, &mut i, name, inline)));
+            }
+            "--memsw-limit" => {
+                opts.memsw_limit = Some(parse_size(name, value(args, &mut i, name, inline)));
+            }
+            "--cpus" => {
+                opts.cpus = Some(parse_list(name, value(args, &mut i, name, inline)));
+            }
+            "--mems" => {
+                opts.mems = Some(parse_list(name, value(args, &mut i, name, inline)));
+            }
+            "--cpu-quota" => {
+                let val = value(args, &mut i, name, inline);
+                // The kernel's smallest quota is 1ms, per 100ms period
+                opts.cpu_quota = match from_str::<f64>(val) {
+                    Some(q) if q >= 0.01 => Some(q),
+                    _ => usage_error(fmt!("Option '%s' expects a number of at least 0.01, got '%s'",
+                                          name, val))
+                };
+            }
+            "--max-pids" => {
+                let n = parse_int(name, value(args, &mut i, name, inline));
+                if n == 0 {
+                    usage_error("--max-pids must be at least 1");
+                }
+                opts.max_pids = Some(n as uint);
+            }
+            "-T" | "--threshold" => {
+                opts.thresholds.push(parse_size(name, value(args, &mut i, name, inline)));
+            }
+            "-r" | "--runs" => {
+                opts.runs = parse_int(name, value(args, &mut i, name, inline)) as uint;
+                if opts.runs == 0 {
+                    usage_error("--runs must be at least 1");
+                }
+            }
+            "-w" | "--warmup" => {
+                opts.warmup = parse_int(name, value(args, &mut i, name, inline)) as uint;
+            }
+            "--kill-grace" => {
+                opts.kill_grace = parse_int(name, value(args, &mut i, name, inline)) as uint;
+            }
+            _ => usage_error(fmt!("Unknown option '%s'", name))
+        }
+
+        i += 1;
+    }
+
+    opts.command = args.slice_from(i).to_owned();
+    if opts.command.len() == 0 {
+        usage_error("No command given");
+    }
+
+    // cgroup2 limits swap on its own, and v1 rejects a memory+swap limit
+    // below the memory limit, which is unlimited unless set
+    match (opts.memory_limit, opts.memsw_limit) {
+        (Some(m), Some(ms)) if ms < m => {
+            usage_error("--memsw-limit can't be below --memory-limit")
+        }
+        (None, Some(_)) => usage_error("--memsw-limit needs --memory-limit"),
+        _ => ()
+    }
+
+    opts
+}
+
+pub fn usage_error(msg: &str) -> ! {
+    io::print("Error: ");
+    io::println(msg);
+    io::print(USAGE);
+    sys::exit(2)
+}
+
+fn value<'a>(args: &'a [~str], i: &mut uint, name: &str, inline: Option<&'a str>) -> &'a str {
+    match inline {
+        Some(v) => v,
+        None => {
+            *i += 1;
+            if *i >= args.len() {
+                usage_error(fmt!("Option '%s' requires a value", name));
+            }
+            let v : &'a str = args[*i];
+            v
+        }
+    }
+}
+
+/// Parses a byte count with an optional K, M or G suffix
+fn parse_size(name: &str, val: &str) -> u64 {
+    if val.len() == 0 {
+        usage_error(fmt!("Option '%s' expects a size", name));
+    }
+    let (num, mult) = match val.char_at_reverse(val.len()) {
+        'k' | 'K' => (val.slice_to(val.len() - 1), 1024),
+        'm' | 'M' => (val.slice_to(val.len() - 1), 1024 * 1024),
+        'g' | 'G' => (val.slice_to(val.len() - 1), 1024 * 1024 * 1024),
+        _ => (val, 1)
+    };
+    parse_int(name, num) as u64 * mult
+}
+
+fn is_psi_resource(r: &str) -> bool {
+    r == "memory" || r == "cpu" || r == "io"
+}
+
+/// Checks `val` is a cpuset list, such as `0-3,6`
+fn parse_list(name: &str, val: &str) -> ~str {
+    match cgroup::parse_list(val) {
+        Some(l) if l.len() > 0 => val.to_owned(),
+        _ => usage_error(fmt!("Option '%s' expects a list such as 0-3,6, got '%s'", name, val))
+    }
+}
+
+fn parse_int(name: &str, val: &str) -> int {
+    match from_str::<int>(val) {
+        Some(n) if n >= 0 => n,
+        _ => usage_error(fmt!("Option '%s' expects a non-negative number, got '%s'", name, val))
+    }
+}
@@ -0,0 +1,244 @@
+use sys::io;
+use trace;
+
+/// A value in the run summary
+#[deriving(Clone, Eq)]
+pub enum Value {
+    Int(i64),
+    Float(f64),
+    Str(~str),
+    Bool(bool),
+    Null
+}
+
+/// What is known about a run before the first sample is taken
+pub struct RunInfo {
+    command: ~[~str],
+    env: ~[~str],
+    cgroup: ~str,
+    interval_us: uint,
+    columns: ~[~str]
+}
+
+/**
+ * An output format for the log. The `Logger` decides what gets recorded and
+ * when, the format only decides how it is written to `out`. `begin` is called
+ * once before any samples and `finish` once after the last one, `note` may be
+ * called at any point in between.
+ */
+pub trait Format {
+    fn begin(&mut self, out: &mut io::BufferedFile, info: &RunInfo);
+    fn sample(&mut self, out: &mut io::BufferedFile, time_ns: u64, values: &[Option<int>]);
+    fn note(&mut self, out: &mut io::BufferedFile, time_ns: u64, msg: &str);
+    fn finish(&mut self, out: &mut io::BufferedFile, summary: &[(&str, Value)]);
+}
+
+/// Picks the format named `name`, `None` if there is no such format
+pub fn by_name(name: &str) -> Option<~Format> {
+    match name {
+        "csv" => Some(~Csv as ~Format),
+        "json" => Some(~Json::new() as ~Format),
+        "binary" => Some(~trace::Writer::new() as ~Format),
+        _ => None
+    }
+}
+
+/// Picks the format from the extension of `path`, CSV unless it is `.json`
+/// or `.gbt`
+pub fn for_path(path: &str) -> ~Format {
+    if path.ends_with(".json") {
+        ~Json::new() as ~Format
+    } else if path.ends_with(".gbt") {
+        ~trace::Writer::new() as ~Format
+    } else {
+        ~Csv as ~Format
+    }
+}
+
+/**
+ * The original output format, one CSV row per sample after a header row.
+ * Metadata, notes and the summary are written as `#` comment lines.
+ */
+pub struct Csv;
+
+impl Format for Csv {
+    fn begin(&mut self, out: &mut io::BufferedFile, info: &RunInfo) {
+        comment(out, "Command: " + info.command.connect(" "));
+        comment(out, "Cgroup: " + info.cgroup);
+        if info.interval_us > 0 {
+            comment(out, fmt!("Sampling interval: %uus", info.interval_us));
+        }
+
+        out.write_str("timestamp_ns");
+        for c in info.columns.iter() {
+            out.write_str(",");
+            out.write_str(*c);
+        }
+        out.write_str("\n");
+    }
+
+    fn sample(&mut self, out: &mut io::BufferedFile, time_ns: u64, values: &[Option<int>]) {
+        out.write_str(time_ns.to_str());
+        for v in values.iter() {
+            out.write_str(",");
+            match *v {
+                Some(v) => { out.write_str(v.to_str()); }
+                None => ()
+            }
+        }
+        out.write_str("\n");
+    }
+
+    fn note(&mut self, out: &mut io::BufferedFile, _time_ns: u64, msg: &str) {
+        comment(out, msg);
+    }
+
+    fn finish(&mut self, out: &mut io::BufferedFile, summary: &[(&str, Value)]) {
+        comment(out, "Summary");
+        for &(name, ref val) in summary.iter() {
+            let val = match *val {
+                Int(n) => n.to_str(),
+                Float(f) => fmt!("%f", f),
+                Str(ref s) => s.clone(),
+                Bool(b) => b.to_str(),
+                Null => ~""
+            };
+            comment(out, fmt!("%s: %s", name, val));
+        }
+    }
+}
+
+fn comment(out: &mut io::BufferedFile, msg: &str) {
+    out.write_str("# ");
+    out.write_str(msg);
+    out.write_str("\n");
+}
+
+/**
+ * A single JSON object per run:
+ *
+ *     {"version": 1, "command": [...], "environment": [...], "cgroup": "...",
+ *      "interval_us": N, "columns": [{"name": "timestamp_ns"}, ...],
+ *      "samples": [[t, v, ...], ...], "events": [{"time_ns": t, "message": "..."}, ...],
+ *      "summary": {...}}
+ *
+ * Samples are streamed out as they are taken. Events can happen between
+ * samples, so they are kept until the samples array has been closed.
+ */
+pub struct Json {
+    priv nsamples: uint,
+    priv events: ~[(u64, ~str)]
+}
+
+impl Json {
+    pub fn new() -> Json {
+        Json { nsamples: 0, events: ~[] }
+    }
+}
+
+impl Format for Json {
+    fn begin(&mut self, out: &mut io::BufferedFile, info: &RunInfo) {
+        out.write_str("{\"version\":1,\"command\":");
+        str_array(out, info.command);
+        out.write_str(",\"environment\":");
+        str_array(out, info.env);
+        out.write_str(",\"cgroup\":");
+        json_str(out, info.cgroup);
+        out.write_str(",\"interval_us\":");
+        out.write_str(info.interval_us.to_str());
+
+        out.write_str(",\"columns\":[{\"name\":\"timestamp_ns\"}");
+        for c in info.columns.iter() {
+            out.write_str(",{\"name\":");
+            json_str(out, *c);
+            out.write_str("}");
+        }
+        out.write_str("],\n\"samples\":[");
+    }
+
+    fn sample(&mut self, out: &mut io::BufferedFile, time_ns: u64, values: &[Option<int>]) {
+        if self.nsamples > 0 {
+            out.write_str(",");
+        }
+        self.nsamples += 1;
+
+        out.write_str("\n[");
+        out.write_str(time_ns.to_str());
+        for v in values.iter() {
+            out.write_str(",");
+            match *v {
+                Some(v) => { out.write_str(v.to_str()); }
+                None => { out.write_str("null"); }
+            }
+        }
+        out.write_str("]");
+    }
+
+    fn note(&mut self, _out: &mut io::BufferedFile, time_ns: u64, msg: &str) {
+        self.events.push((time_ns, msg.to_owned()));
+    }
+
+    fn finish(&mut self, out: &mut io::BufferedFile, summary: &[(&str, Value)]) {
+        out.write_str("],\n\"events\":[");
+        for (i, &(t, ref msg)) in self.events.iter().enumerate() {
+            if i > 0 {
+                out.write_str(",");
+            }
+            out.write_str("\n{\"time_ns\":");
+            out.write_str(t.to_str());
+            out.write_str(",\"message\":");
+            json_str(out, *msg);
+            out.write_str("}");
+        }
+
+        out.write_str("],\n\"summary\":{");
+        for (i, &(name, ref val)) in summary.iter().enumerate() {
+            if i > 0 {
+                out.write_str(",");
+            }
+            json_str(out, name);
+            out.write_str(":");
+            match *val {
+                Int(n) => { out.write_str(n.to_str()); }
+                Float(f) => { out.write_str(fmt!("%f", f)); }
+                Str(ref s) => json_str(out, *s),
+                Bool(b) => { out.write_str(b.to_str()); }
+                Null => { out.write_str("null"); }
+            }
+        }
+        out.write_str("}}\n");
+    }
+}
+
+fn str_array(out: &mut io::BufferedFile, items: &[~str]) {
+    out.write_str("[");
+    for (i, s) in items.iter().enumerate() {
+        if i > 0 {
+            out.write_str(",");
+        }
+        json_str(out, *s);
+    }
+    out.write_str("]");
+}
+
+/// Writes `s` as a JSON string literal
+fn json_str(out: &mut io::BufferedFile, s: &str) {
+    static HEX : &'static [u8] = bytes!("0123456789abcdef");
+
+    out.write_str("\"");
+    let mut start = 0;
+    for (i, &b) in s.as_bytes().iter().enumerate() {
+        if b == '"' as u8 || b == '\\' as u8 || b < 0x20 {
+            out.write_bytes(s.as_bytes().slice(start, i));
+            if b == '"' as u8 || b == '\\' as u8 {
+                out.write_bytes(['\\' as u8, b]);
+            } else {
+                out.write_bytes(['\\' as u8, 'u' as u8, '0' as u8, '0' as u8,
+                                 HEX[(b >> 4) as uint], HEX[(b & 0xf) as uint]]);
+            }
+            start = i + 1;
+        }
+    }
+    out.write_bytes(s.as_bytes().slice_from(start));
+    out.write_str("\"");
+}
@@ -0,0 +1,137 @@
+use cgroup;
+use sys;
+use sys::io;
+use sys::err::{Ok, Err};
+use sys::perf::{Counter, TYPE_HARDWARE, TYPE_SOFTWARE};
+use sys::perf::{COUNT_HW_INSTRUCTIONS, COUNT_HW_CPU_CYCLES, COUNT_HW_CACHE_MISSES};
+use sys::perf::{COUNT_HW_BRANCH_MISSES, COUNT_SW_TASK_CLOCK, COUNT_SW_PAGE_FAULTS};
+use sys::perf::{COUNT_SW_CONTEXT_SWITCHES, ATTR_DISABLED, ATTR_INHERIT, ATTR_ENABLE_ON_EXEC};
+use sys::perf::{ATTR_EXCLUDE_HV, FLAG_PID_CGROUP};
+
+use std::libc::pid_t;
+
+/// The events that can be counted, by name
+static EVENTS : &'static [(&'static str, u32, u64)] = &[
+    ("instructions",        TYPE_HARDWARE,  COUNT_HW_INSTRUCTIONS),
+    ("cycles",              TYPE_HARDWARE,  COUNT_HW_CPU_CYCLES),
+    ("cache-misses",        TYPE_HARDWARE,  COUNT_HW_CACHE_MISSES),
+    ("branch-misses",       TYPE_HARDWARE,  COUNT_HW_BRANCH_MISSES),
+    ("task-clock",          TYPE_SOFTWARE,  COUNT_SW_TASK_CLOCK),
+    ("page-faults",         TYPE_SOFTWARE,  COUNT_SW_PAGE_FAULTS),
+    ("context-switches",    TYPE_SOFTWARE,  COUNT_SW_CONTEXT_SWITCHES),
+];
+
+/// Whether `name` is an event `Counters` can count
+pub fn is_event(name: &str) -> bool {
+    EVENTS.iter().any(|&(n, _, _)| n == name)
+}
+
+/// The names of all events, for `--perf all`
+pub fn all_events() -> ~[~str] {
+    EVENTS.iter().map(|&(n, _, _)| n.to_owned()).collect()
+}
+
+/**
+ * A set of perf counters for the command. Events that can't be opened, such
+ * as the hardware events in a VM without a PMU, are skipped with a warning,
+ * the software events are always available.
+ */
+pub struct Counters {
+    priv events: ~[(&'static str, ~[Counter])]
+}
+
+impl Counters {
+    /**
+     * Counts process `pid` and every process it starts. The counters start
+     * when `pid` calls execve. Counts of child processes are only added once
+     * they exit.
+     */
+    pub fn for_process(names: &[~str], pid: pid_t) -> Counters {
+        let flags = ATTR_DISABLED | ATTR_INHERIT | ATTR_ENABLE_ON_EXEC | ATTR_EXCLUDE_HV;
+        Counters::open(names, |kind, config| {
+            match Counter::open(kind, config, flags, pid as int, -1, 0) {
+                Ok(c) => Ok(~[c]),
+                Err(e) => Err(e)
+            }
+        })
+    }
+
+    /**
+     * Counts whatever runs in `group`, which needs a counter on each CPU. On
+     * v1 the group has to have the `perf_event` controller.
+     */
+    pub fn for_group(names: &[~str], group: &cgroup::Group) -> Counters {
+        let dir = match group.perf_dir() {
+            Ok(d) => d,
+            Err(e) => sys::fail(fmt!("Could not open the cgroup for perf events (%s)", e.msg()))
+        };
+        let cpus = match io::read_file("/sys/devices/system/cpu/online") {
+            Ok(s) => cgroup::parse_list(s.trim()).unwrap_or(~[]),
+            Err(e) => sys::fail(fmt!("Could not read the online CPUs (%s)", e.msg()))
+        };
+
+        Counters::open(names, |kind, config| {
+            let mut counters = ~[];
+            let mut err = None;
+            for &cpu in cpus.iter() {
+                if err.is_none() {
+                    match Counter::open(kind, config, ATTR_EXCLUDE_HV, dir.fd(), cpu as int,
+                                        FLAG_PID_CGROUP) {
+                        Ok(c) => counters.push(c),
+                        Err(e) => err = Some(e)
+                    }
+                }
+            }
+            match err {
+                Some(e) => Err(e),
+                None => Ok(counters)
+            }
+        })
+    }
+
+    fn open(names: &[~str],
+            open: &fn(u32, u64) -> sys::err::ErrorResult<~[Counter]>) -> Counters {
+        let mut events = ~[];
+        for &(name, kind, config) in EVENTS.iter() {
+            if names.iter().any(|n| n.equiv(&name)) {
+                match open(kind, config) {
+                    Ok(c) => events.push((name, c)),
+                    Err(e) => io::println(fmt!("Warning could not open perf event %s (%s)",
+                                               name, e.msg()))
+                }
+            }
+        }
+        Counters { events: events }
+    }
+
+    pub fn columns(&self) -> ~[~str] {
+        self.events.iter().map(|&(name, _)| "perf." + name).collect()
+    }
+
+    /// Appends the current count of each event to `row`
+    pub fn sample(&self, row: &mut ~[Option<int>]) {
+        for &(_, ref counters) in self.events.iter() {
+            row.push(read_sum(*counters).map(|&v| v as int));
+        }
+    }
+
+    /// The count of each event, for the summary
+    pub fn totals(&self) -> ~[(&'static str, u64)] {
+        self.events.iter().map(|&(name, ref counters)| {
+            (name, read_sum(*counters).unwrap_or(0))
+        }).collect()
+    }
+}
+
+/// The sum of the counts of one event on several CPUs, `None` if any of
+/// them can't be read
+fn read_sum(counters: &[Counter]) -> Option<u64> {
+    let mut total = 0;
+    for c in counters.iter() {
+        match c.read() {
+            Ok(v) => total += v,
+            Err(_) => return None
+        }
+    }
+    Some(total)
+}
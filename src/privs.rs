@@ -0,0 +1,149 @@
+use sys;
+use sys::io;
+use sys::err::{Ok, Err};
+use std::from_str::from_str;
+
+static PASSWD : &'static str = "/etc/passwd";
+static GROUP : &'static str = "/etc/group";
+
+/**
+ * The identity the benchmarked command is run as. Resolved in the parent,
+ * before forking, so lookup errors are reported before anything is started.
+ */
+pub struct Credentials {
+    name: ~str,
+    uid: int,
+    gid: int,
+    groups: ~[u32]
+}
+
+/**
+ * Resolves `user` (a name or a numeric uid) and the optional `group` (a name
+ * or a numeric gid) to a set of credentials. If no group is given the user's
+ * primary group from /etc/passwd is used, or the gid equal to the uid for a
+ * uid with no entry. Supplementary groups are taken from /etc/group.
+ */
+pub fn resolve(user: &str, group: Option<&str>) -> Credentials {
+    let passwd = read_db(PASSWD);
+    let groups = read_db(GROUP);
+
+    let (name, uid, pgid) = match find_entry(passwd, user) {
+        Some(fields) => {
+            if fields.len() < 4 {
+                sys::fail(fmt!("Malformed %s entry for '%s'", PASSWD, user));
+            }
+            (fields[0].to_owned(), to_id(fields[2]), Some(to_id(fields[3])))
+        }
+        None => match from_str::<int>(user) {
+            Some(uid) if uid >= 0 => (user.to_owned(), uid, None),
+            _ => sys::fail(fmt!("Unknown user '%s'", user))
+        }
+    };
+
+    let gid = match group {
+        Some(g) => match find_entry(groups, g) {
+            Some(fields) if fields.len() >= 3 => to_id(fields[2]),
+            _ => match from_str::<int>(g) {
+                Some(gid) if gid >= 0 => gid,
+                _ => sys::fail(fmt!("Unknown group '%s'", g))
+            }
+        },
+        None => pgid.unwrap_or(uid)
+    };
+
+    let mut supp = ~[gid as u32];
+    for line in groups.line_iter() {
+        let fields : ~[&str] = line.split_iter(':').collect();
+        if fields.len() >= 4 && fields[3].split_iter(',').any(|m| name.equiv(&m)) {
+            let g = to_id(fields[2]) as u32;
+            if !supp.contains(&g) {
+                supp.push(g);
+            }
+        }
+    }
+
+    Credentials {
+        name: name,
+        uid: uid,
+        gid: gid,
+        groups: supp
+    }
+}
+
+/**
+ * Permanently switches the current process to `creds`. The supplementary
+ * groups and the gid are set before the uid, since changing them requires the
+ * privileges dropped by the last step. Fails if any step, or the check that
+ * the old identity cannot be regained, does not succeed.
+ */
+pub fn drop_to(creds: &Credentials) {
+    let (uid, gid) = (creds.uid, creds.gid);
+
+    if sys::getresuid() == (uid, uid, uid) && sys::getresgid() == (gid, gid, gid) {
+        return;
+    }
+
+    let res = sys::setgroups(creds.groups);
+    if res.is_err() {
+        drop_failed(creds, "setgroups", res.msg());
+    }
+    let res = sys::setresgid(gid, gid, gid);
+    if res.is_err() {
+        drop_failed(creds, "setresgid", res.msg());
+    }
+    let res = sys::setresuid(uid, uid, uid);
+    if res.is_err() {
+        drop_failed(creds, "setresuid", res.msg());
+    }
+
+    if sys::getresgid() != (gid, gid, gid) {
+        drop_failed(creds, "verify", "gid was not changed");
+    }
+    if sys::getresuid() != (uid, uid, uid) {
+        drop_failed(creds, "verify", "uid was not changed");
+    }
+
+    if sys::getgroups([]) != creds.groups.len() as int {
+        drop_failed(creds, "verify", "supplementary groups were not changed");
+    }
+
+    if uid != 0 && !sys::setresuid(0, 0, 0).is_err() {
+        drop_failed(creds, "verify", "root privileges could be regained");
+    }
+}
+
+fn drop_failed(creds: &Credentials, step: &str, msg: &str) -> ! {
+    sys::fail(fmt!("Could not drop privileges to %s (uid %d, gid %d): %s: %s",
+                   creds.name, creds.uid, creds.gid, step, msg))
+}
+
+fn read_db(path: &str) -> ~str {
+    match io::read_file(path) {
+        Ok(s) => s,
+        Err(e) => {
+            if !e.is(sys::err::ENOENT) {
+                sys::fail(fmt!("Could not read %s (%s)", path, e.msg()));
+            }
+            ~""
+        }
+    }
+}
+
+/// Finds the line in a passwd/group style database whose name, or numeric
+/// id, matches `key`.
+fn find_entry<'a>(db: &'a str, key: &str) -> Option<~[&'a str]> {
+    for line in db.line_iter() {
+        let fields : ~[&'a str] = line.split_iter(':').collect();
+        if fields.len() >= 3 && (fields[0] == key || fields[2] == key) {
+            return Some(fields);
+        }
+    }
+    None
+}
+
+fn to_id(s: &str) -> int {
+    match from_str::<int>(s) {
+        Some(id) if id >= 0 => id,
+        _ => sys::fail(fmt!("Invalid id '%s'", s))
+    }
+}
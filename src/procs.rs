@@ -0,0 +1,160 @@
+use cgroup;
+use sys::io;
+use sys::err::{Ok, Err};
+
+use std::vec;
+
+/// The memory of the processes running one command, summed, in kilobytes
+#[deriving(Clone)]
+pub struct ProcMem {
+    name: ~str,
+    procs: uint,
+    rss_kb: uint,
+    pss_kb: uint,
+    swap_kb: uint,
+    anon_kb: uint
+}
+
+/**
+ * Breaks the group's memory down by process. Every sample lists the
+ * processes in the group and reads `/proc/<pid>/status` for RSS, anonymous
+ * memory and swap, and `/proc/<pid>/smaps_rollup` for PSS. The totals are
+ * logged along with a note breaking them down by command name, and the
+ * breakdown is kept from the sample where the total PSS was highest.
+ */
+pub struct ProcSampler {
+    priv procs: cgroup::Monitor,
+    priv buf: ~[u8],
+    priv pids: ~[int],
+    priv current: ~[ProcMem],
+    priv peak: ~[ProcMem],
+    priv peak_pss: uint
+}
+
+impl ProcSampler {
+    pub fn new(group: &cgroup::Group) -> ProcSampler {
+        ProcSampler {
+            procs: group.monitor("memory", "cgroup.procs"),
+            buf: vec::from_elem(65536, 0u8),
+            pids: ~[],
+            current: ~[],
+            peak: ~[],
+            peak_pss: 0
+        }
+    }
+
+    pub fn columns() -> ~[~str] {
+        ~[~"procs.rss_kb", ~"procs.pss_kb", ~"procs.swap_kb", ~"procs.anon_kb"]
+    }
+
+    /// Reads the memory of every process in the group and appends the totals
+    /// to `row`
+    pub fn sample(&mut self, row: &mut ~[Option<int>]) {
+        self.procs.get_ints(&mut self.buf, &mut self.pids);
+        self.current.clear();
+
+        let mut total = ProcMem { name: ~"", procs: 0, rss_kb: 0, pss_kb: 0, swap_kb: 0, anon_kb: 0 };
+        for &pid in self.pids.iter() {
+            // The process may have exited since the group was listed
+            match read_proc(pid) {
+                Some(m) => {
+                    add(&mut total, &m);
+                    match self.current.iter().position(|c| c.name == m.name) {
+                        Some(i) => add(&mut self.current[i], &m),
+                        None => self.current.push(m)
+                    }
+                }
+                None => ()
+            }
+        }
+
+        if total.pss_kb > self.peak_pss {
+            self.peak_pss = total.pss_kb;
+            self.peak = self.current.clone();
+        }
+
+        row.push(Some(total.rss_kb as int));
+        row.push(Some(total.pss_kb as int));
+        row.push(Some(total.swap_kb as int));
+        row.push(Some(total.anon_kb as int));
+    }
+
+    /// The memory of each command at the sample with the highest total PSS,
+    /// largest PSS first
+    pub fn peak(&self) -> ~[ProcMem] {
+        by_pss(self.peak.clone())
+    }
+
+    /**
+     * The memory of each command at the last sample, largest PSS first, as a
+     * note for the log, such as
+     * `cc1plus (8) RSS 2411520 PSS 2398208 anon 2301952 swap 0, ld (1) ...`
+     * with the sizes in kilobytes. Empty if the group had no processes.
+     */
+    pub fn note(&self) -> ~str {
+        let parts : ~[~str] = by_pss(self.current.clone()).iter().map(|p| {
+            fmt!("%s (%u) RSS %u PSS %u anon %u swap %u",
+                 p.name, p.procs, p.rss_kb, p.pss_kb, p.anon_kb, p.swap_kb)
+        }).collect();
+        parts.connect(", ")
+    }
+}
+
+/// Sorts `v` by PSS, largest first
+fn by_pss(mut v: ~[ProcMem]) -> ~[ProcMem] {
+    for i in range(1, v.len()) {
+        let mut j = i;
+        while j > 0 && v[j - 1].pss_kb < v[j].pss_kb {
+            v.swap(j - 1, j);
+            j -= 1;
+        }
+    }
+    v
+}
+
+fn add(total: &mut ProcMem, m: &ProcMem) {
+    total.procs += m.procs;
+    total.rss_kb += m.rss_kb;
+    total.pss_kb += m.pss_kb;
+    total.swap_kb += m.swap_kb;
+    total.anon_kb += m.anon_kb;
+}
+
+/// The memory of process `pid`, `None` if it is gone
+fn read_proc(pid: int) -> Option<ProcMem> {
+    let status = match io::read_file(fmt!("/proc/%d/status", pid)) {
+        Ok(s) => s,
+        Err(_) => return None
+    };
+
+    // Kernel threads have no smaps_rollup, and kernels before 4.14 none at all
+    let pss = match io::read_file(fmt!("/proc/%d/smaps_rollup", pid)) {
+        Ok(s) => kb_field(s, "Pss:"),
+        Err(_) => 0
+    };
+
+    let name = status.line_iter().find(|l| l.starts_with("Name:"))
+                     .map_default(~"?", |l| l.slice_from(5).trim().to_owned());
+
+    Some(ProcMem {
+        name: name,
+        procs: 1,
+        rss_kb: kb_field(status, "VmRSS:"),
+        pss_kb: pss,
+        swap_kb: kb_field(status, "VmSwap:"),
+        anon_kb: kb_field(status, "RssAnon:")
+    })
+}
+
+/// The value of a `Key:    1234 kB` line, 0 if there is none
+fn kb_field(contents: &str, key: &str) -> uint {
+    use std::from_str::from_str;
+
+    match contents.line_iter().find(|l| l.starts_with(key)) {
+        Some(l) => match l.slice_from(key.len()).word_iter().next() {
+            Some(n) => from_str::<uint>(n).unwrap_or(0),
+            None => 0
+        },
+        None => 0
+    }
+}
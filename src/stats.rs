@@ -0,0 +1,75 @@
+/// Two-sided 95% critical values of Student's t distribution for 1 to 30
+/// degrees of freedom, beyond that the normal approximation is used.
+static T_95 : &'static [f64] = &[
+    12.706, 4.303, 3.182, 2.776, 2.571, 2.447, 2.365, 2.306, 2.262, 2.228,
+    2.201, 2.179, 2.160, 2.145, 2.131, 2.120, 2.110, 2.101, 2.093, 2.086,
+    2.080, 2.074, 2.069, 2.064, 2.060, 2.056, 2.052, 2.048, 2.045, 2.042
+];
+
+pub struct Summary {
+    n: uint,
+    mean: f64,
+    median: f64,
+    stddev: f64,
+    min: f64,
+    max: f64,
+    /// Half-width of the 95% confidence interval of the mean
+    ci95: f64
+}
+
+pub fn summarize(samples: &[f64]) -> Summary {
+    let n = samples.len();
+    if n == 0 {
+        return Summary { n: 0, mean: 0.0, median: 0.0, stddev: 0.0, min: 0.0, max: 0.0, ci95: 0.0 };
+    }
+
+    let mut sorted = samples.to_owned();
+    insertion_sort(sorted);
+
+    let mut total = 0.0;
+    for &x in samples.iter() {
+        total += x;
+    }
+    let mean = total / n as f64;
+
+    let median = if n % 2 == 1 {
+        sorted[n / 2]
+    } else {
+        (sorted[n / 2 - 1] + sorted[n / 2]) / 2.0
+    };
+
+    let (stddev, ci95) = if n > 1 {
+        let mut sq = 0.0;
+        for &x in samples.iter() {
+            sq += (x - mean) * (x - mean);
+        }
+        let sd = (sq / (n - 1) as f64).sqrt();
+        let t = if n - 1 <= T_95.len() { T_95[n - 2] } else { 1.960 };
+        (sd, t * sd / (n as f64).sqrt())
+    } else {
+        (0.0, 0.0)
+    };
+
+    Summary {
+        n: n,
+        mean: mean,
+        median: median,
+        stddev: stddev,
+        min: sorted[0],
+        max: sorted[n - 1],
+        ci95: ci95
+    }
+}
+
+// The number of runs is small, so this doesn't need to be clever
+fn insertion_sort(v: &mut [f64]) {
+    for i in range(1, v.len()) {
+        let x = v[i];
+        let mut j = i;
+        while j > 0 && v[j - 1] > x {
+            v[j] = v[j - 1];
+            j -= 1;
+        }
+        v[j] = x;
+    }
+}
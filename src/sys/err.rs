@@ -11,6 +11,18 @@ pub enum ErrorResult<T> {
 }
 
 impl Error {
+    /// The error of a call that succeeded
+    #[inline(always)]
+    pub fn ok() -> Error {
+        Error { n: 0 }
+    }
+
+    /// The error of a call that failed with errno `code`
+    #[inline(always)]
+    pub fn errno(code: int) -> Error {
+        Error { n: -code }
+    }
+
     #[inline(always)]
     pub fn is_err(&self) -> bool {
         self.n != 0
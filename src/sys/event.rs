@@ -0,0 +1,207 @@
+use sys;
+use sys::err::{Error, ErrorResult, Err, Ok};
+use sys::io::raw;
+use sys::n;
+use sys::{syscall1,syscall2,syscall4};
+
+use std::cast;
+
+pub static EFD_SEMAPHORE        : int = 0x000001;
+pub static EFD_NONBLOCK         : int = 0x000800;
+pub static EFD_CLOEXEC          : int = 0x080000;
+
+/**
+ * A counter the kernel, or another process, can signal. Used to receive
+ * cgroup notifications.
+ */
+#[unsafe_no_drop_flag]
+pub struct EventFd {
+    priv fd: int
+}
+
+impl EventFd {
+    pub fn new(flags: int) -> ErrorResult<EventFd> {
+        unsafe {
+            let fd = syscall2(n::EVENTFD2, 0, flags);
+            if fd < 0 {
+                Err(cast::transmute(fd))
+            } else {
+                Ok(EventFd { fd: fd })
+            }
+        }
+    }
+
+    /**
+     * Reads and resets the counter. If the eventfd is non-blocking this fails
+     * with EAGAIN when there are no pending events.
+     */
+    pub fn read(&self) -> ErrorResult<u64> {
+        let mut buf = [0u8,..8];
+        let ret = raw::read(self.fd, buf);
+
+        unsafe {
+            if ret < 0 {
+                Err(cast::transmute(ret))
+            } else {
+                Ok(cast::transmute(buf))
+            }
+        }
+    }
+
+    pub fn fd(&self) -> int {
+        self.fd
+    }
+}
+
+impl Drop for EventFd {
+    fn drop(&self) {
+        if self.fd != 0 {
+            raw::close(self.fd);
+        }
+    }
+}
+
+pub static SFD_NONBLOCK         : int = 0x000800;
+pub static SFD_CLOEXEC          : int = 0x080000;
+
+/// Size of struct signalfd_siginfo, one is read per pending signal
+static SIGINFO_SIZE : uint = 128;
+
+/**
+ * Receives the signals in a set, see `sig::set`, as reads instead of
+ * handlers. The signals have to be blocked, or they are delivered as usual.
+ */
+#[unsafe_no_drop_flag]
+pub struct SignalFd {
+    priv fd: int
+}
+
+impl SignalFd {
+    pub fn new(set: u64) -> ErrorResult<SignalFd> {
+        unsafe {
+            let setp : *u64 = &set;
+            let fd = syscall4(n::SIGNALFD4, -1, setp as int, 8, SFD_NONBLOCK | SFD_CLOEXEC);
+            if fd < 0 {
+                Err(cast::transmute(fd))
+            } else {
+                Ok(SignalFd { fd: fd })
+            }
+        }
+    }
+
+    /// Reads every pending signal, returns how many there were
+    pub fn drain(&self) -> uint {
+        let mut buf = [0u8,..SIGINFO_SIZE];
+        let mut count = 0;
+        while raw::read(self.fd, buf) == SIGINFO_SIZE as int {
+            count += 1;
+        }
+        count
+    }
+
+    pub fn fd(&self) -> int {
+        self.fd
+    }
+}
+
+impl Drop for SignalFd {
+    fn drop(&self) {
+        if self.fd != 0 {
+            raw::close(self.fd);
+        }
+    }
+}
+
+pub static EPOLLIN              : int = 0x001;
+pub static EPOLLPRI             : int = 0x002;
+pub static EPOLLOUT             : int = 0x004;
+pub static EPOLLERR             : int = 0x008;
+pub static EPOLLHUP             : int = 0x010;
+
+pub static EPOLL_CLOEXEC        : int = 0x080000;
+
+pub static EPOLL_CTL_ADD        : int = 1;
+pub static EPOLL_CTL_DEL        : int = 2;
+pub static EPOLL_CTL_MOD        : int = 3;
+
+// struct epoll_event is packed on x86-64, a u32 of events and a u64 of data
+static EVENT_SIZE : uint = 12;
+static MAX_EVENTS : uint = 16;
+
+/**
+ * Waits on several file descriptors at once. Each descriptor is registered
+ * with a token, `wait` reports which descriptors are ready by their tokens.
+ */
+#[unsafe_no_drop_flag]
+pub struct Epoll {
+    priv fd: int
+}
+
+impl Epoll {
+    pub fn new() -> ErrorResult<Epoll> {
+        unsafe {
+            let fd = syscall1(n::EPOLL_CREATE1, EPOLL_CLOEXEC);
+            if fd < 0 {
+                Err(cast::transmute(fd))
+            } else {
+                Ok(Epoll { fd: fd })
+            }
+        }
+    }
+
+    pub fn add(&self, fd: int, events: int, token: u64) -> Error {
+        unsafe {
+            let mut ev = [0u8,..EVENT_SIZE];
+            let evs : [u8,..4] = cast::transmute(events as u32);
+            let tok : [u8,..8] = cast::transmute(token);
+            for i in range(0u, 4) {
+                ev[i] = evs[i];
+            }
+            for i in range(0u, 8) {
+                ev[4 + i] = tok[i];
+            }
+            let evp : int = cast::transmute(&ev);
+
+            cast::transmute(syscall4(n::EPOLL_CTL, self.fd, EPOLL_CTL_ADD, fd, evp))
+        }
+    }
+
+    /**
+     * Waits up to `timeout_ms` milliseconds, -1 meaning forever, for any of
+     * the registered descriptors to become ready. The tokens of the ready
+     * ones are stored in `tokens`, and their number returned. Being
+     * interrupted by a signal counts as nothing being ready.
+     */
+    pub fn wait(&self, tokens: &mut [u64], timeout_ms: int) -> ErrorResult<uint> {
+        unsafe {
+            let mut evs = [0u8,..EVENT_SIZE * MAX_EVENTS];
+            let max = if tokens.len() < MAX_EVENTS { tokens.len() } else { MAX_EVENTS };
+            let evp : int = cast::transmute(&mut evs);
+
+            let ret = syscall4(n::EPOLL_WAIT, self.fd, evp, max as int, timeout_ms);
+            if ret == -sys::err::EINTR {
+                return Ok(0);
+            } else if ret < 0 {
+                return Err(cast::transmute(ret));
+            }
+
+            for i in range(0, ret as uint) {
+                let mut tok = [0u8,..8];
+                for j in range(0u, 8) {
+                    tok[j] = evs[i * EVENT_SIZE + 4 + j];
+                }
+                tokens[i] = cast::transmute(tok);
+            }
+
+            Ok(ret as uint)
+        }
+    }
+}
+
+impl Drop for Epoll {
+    fn drop(&self) {
+        if self.fd != 0 {
+            raw::close(self.fd);
+        }
+    }
+}
@@ -92,12 +92,50 @@ impl File {
         }
     }
 
+    pub fn read_to_end(&self) -> ErrorResult<~[u8]> {
+        let mut out : ~[u8] = ~[];
+        let mut buf = [0u8,..4096];
+
+        loop {
+            match self.read_bytes(buf) {
+                Ok(0) => break,
+                Ok(n) => out.push_all(buf.slice_to(n as uint)),
+                Err(e) => return Err(e)
+            }
+        }
+
+        Ok(out)
+    }
+
+    pub fn read_to_str(&self) -> ErrorResult<~str> {
+        use std::{str,vec};
+
+        match self.read_to_end() {
+            Ok(out) => unsafe {
+                Ok(str::raw::from_buf_len(vec::raw::to_ptr(out), out.len()))
+            },
+            Err(e) => Err(e)
+        }
+    }
+
+    /// Takes an exclusive `flock` on the file, failing with EAGAIN instead
+    /// of blocking if someone else holds it.
+    pub fn try_lock(&self) -> Error {
+        unsafe {
+            cast::transmute(syscall2(n::FLOCK, self.fd, raw::LOCK_EX | raw::LOCK_NB))
+        }
+    }
+
     pub fn reset(&self) -> Error {
         unsafe {
             cast::transmute(raw::lseek(self.fd, 0, 0))
         }
     }
 
+    pub fn fd(&self) -> int {
+        self.fd
+    }
+
     pub fn close(self) { }
 }
 
@@ -124,6 +162,126 @@ impl Clone for File {
     }
 }
 
+pub static BUF_SIZE : uint = 8192;
+
+/**
+ * A write buffer in front of a `File`. Data is only written out when the
+ * buffer fills up, on an explicit `flush`, when `flush_if_due` finds the
+ * flush policy has been met, or when the `BufferedFile` is dropped.
+ */
+#[unsafe_no_drop_flag]
+pub struct BufferedFile {
+    priv file: File,
+    priv buf: ~[u8],
+    priv len: uint,
+    priv flush_size: uint,
+    priv flush_interval: u64,
+    priv last_flush: u64
+}
+
+impl BufferedFile {
+    pub fn new(file: File, capacity: uint) -> BufferedFile {
+        use std::vec;
+
+        BufferedFile {
+            file: file,
+            buf: vec::from_elem(capacity, 0u8),
+            len: 0,
+            flush_size: capacity,
+            flush_interval: 0,
+            last_flush: 0
+        }
+    }
+
+    pub fn open(path: &str, mode: &str) -> ErrorResult<BufferedFile> {
+        match File::open(path, mode) {
+            Ok(f) => Ok(BufferedFile::new(f, BUF_SIZE)),
+            Err(e) => Err(e)
+        }
+    }
+
+    /**
+     * Sets when `flush_if_due` writes the buffer out: once it holds at least
+     * `size` bytes, or once `interval_ns` nanoseconds have passed since the
+     * last flush. An interval of 0 disables the time based flush.
+     */
+    pub fn set_flush_policy(&mut self, size: uint, interval_ns: u64) {
+        self.flush_size = size;
+        self.flush_interval = interval_ns;
+    }
+
+    pub fn write_str(&mut self, s: &str) -> ErrorResult<int> {
+        unsafe {
+            self.write_bytes(cast::transmute(s))
+        }
+    }
+
+    pub fn write_bytes(&mut self, bytes: &[u8]) -> ErrorResult<int> {
+        let cap = self.buf.len();
+
+        if self.len + bytes.len() > cap {
+            let err = self.flush();
+            if err.is_err() {
+                return Err(err);
+            }
+        }
+
+        if bytes.len() > cap {
+            return self.file.write_bytes(bytes);
+        }
+
+        for (i, &b) in bytes.iter().enumerate() {
+            self.buf[self.len + i] = b;
+        }
+        self.len += bytes.len();
+
+        Ok(bytes.len() as int)
+    }
+
+    pub fn flush(&mut self) -> Error {
+        let mut off = 0;
+        while off < self.len {
+            match self.file.write_bytes(self.buf.slice(off, self.len)) {
+                Ok(n) => off += n as uint,
+                Err(e) => {
+                    self.len = 0;
+                    return e;
+                }
+            }
+        }
+        self.len = 0;
+
+        Error::ok()
+    }
+
+    /// Flushes the buffer if the flush policy says so, `now` is the current
+    /// `sys::getclock` time.
+    pub fn flush_if_due(&mut self, now: u64) -> Error {
+        let due = self.len >= self.flush_size ||
+            (self.flush_interval > 0 && now - self.last_flush >= self.flush_interval);
+
+        if due {
+            self.last_flush = now;
+            self.flush()
+        } else {
+            Error::ok()
+        }
+    }
+}
+
+impl Drop for BufferedFile {
+    fn drop(&self) {
+        let mut off = 0;
+        while off < self.len {
+            let ret = raw::write(self.file.fd, self.buf.slice(off, self.len));
+            if ret <= 0 {
+                break;
+            }
+            off += ret as uint;
+        }
+    }
+}
+
 fn fmodeflags(mode: &str) -> int {
     let mut flags = 0;
 
@@ -157,9 +315,20 @@ fn fmodeflags(mode: &str) -> int {
     flags
 }
 
+pub fn read_file(path: &str) -> ErrorResult<~str> {
+    match File::open(path, "r") {
+        Ok(f) => f.read_to_str(),
+        Err(e) => Err(e)
+    }
+}
+
 #[inline]
 pub fn mkdir(path: &str, mode: int) -> Error {
     unsafe {
+        // The name has to fit in the buffer with its terminating NUL
+        if path.len() >= FILENAME_MAX as uint {
+            return cast::transmute(-sys::err::ENAMETOOLONG);
+        }
         let mut name = [0u8,..FILENAME_MAX];
         let nmp : *mut u8 = cast::transmute(&mut name);
         let (ptr, len) : (*u8, uint) = cast::transmute(path);
@@ -172,6 +341,10 @@ pub fn mkdir(path: &str, mode: int) -> Error {
 #[inline]
 pub fn rmdir(path: &str) -> Error {
     unsafe {
+        // The name has to fit in the buffer with its terminating NUL
+        if path.len() >= FILENAME_MAX as uint {
+            return cast::transmute(-sys::err::ENAMETOOLONG);
+        }
         let mut name = [0u8,..FILENAME_MAX];
         let nmp : *mut u8 = cast::transmute(&mut name);
         let (ptr, len) : (*u8, uint) = cast::transmute(path);
@@ -181,7 +354,24 @@ pub fn rmdir(path: &str) -> Error {
     }
 }
 
+/// Only the tests create files they have to remove again
+#[cfg(test)]
+pub fn unlink(path: &str) -> Error {
+    unsafe {
+        if path.len() >= FILENAME_MAX as uint {
+            return cast::transmute(-sys::err::ENAMETOOLONG);
+        }
+        let mut name = [0u8,..FILENAME_MAX];
+        let nmp : *mut u8 = cast::transmute(&mut name);
+        let (ptr, len) : (*u8, uint) = cast::transmute(path);
+        ptr::copy_memory(nmp, ptr, len);
+
+        cast::transmute(syscall1(n::UNLINK, nmp as int))
+    }
+}
+
 pub mod raw {
+    use sys::err;
     use sys::n;
     use sys::{syscall1,syscall3};
     use std::{cast,ptr};
@@ -204,6 +394,9 @@ pub mod raw {
     pub static O_ASYNC          : int = 0x002000;
     pub static O_LARGEFILE      : int = 0x008000;
 
+    pub static LOCK_EX          : int = 2;
+    pub static LOCK_NB          : int = 4;
+
     #[inline]
     pub fn write(fd: int, s: &[u8]) -> int {
         unsafe {
@@ -230,6 +423,9 @@ pub mod raw {
     #[inline]
     pub fn open(path: &str, flags: int, mode: int) -> int {
         unsafe {
+            if path.len() >= FILENAME_MAX as uint {
+                return -err::ENAMETOOLONG;
+            }
             let mut name = [0u8,..FILENAME_MAX];
             let nmp : *mut u8 = cast::transmute(&mut name);
             let (ptr, len) : (*u8, uint) = cast::transmute(path);
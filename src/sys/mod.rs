@@ -4,6 +4,9 @@ use std::libc::{pid_t};
 pub mod io;
 pub mod sig;
 pub mod err;
+pub mod timer;
+pub mod event;
+pub mod perf;
 
 #[inline(always)]
 pub unsafe fn syscall0(n: int) -> int {
@@ -91,15 +94,57 @@ pub fn pause() {
     }
 }
 
+pub static WNOHANG : int = 1;
+
 #[inline]
 pub fn waitpid(pid: pid_t, options: int) -> (int, int) {
     unsafe {
-        let mut stat = -1;
-        let statp : *mut int = &mut stat;
+        let mut stat = 0i32;
+        let statp : *mut i32 = &mut stat;
 
         let ret = syscall4(n::WAIT4, pid as int, statp as int, options, 0);
 
-        (stat, ret)
+        (stat as int, ret)
+    }
+}
+
+/// How a child process ended, decoded from the status word of `waitpid`
+pub enum ExitStatus {
+    /// Exited normally, with the given exit code
+    Exited(int),
+    /// Terminated by a signal, and whether it dumped core
+    Signaled(int, bool),
+    /// Stopped by a signal
+    Stopped(int)
+}
+
+impl ExitStatus {
+    pub fn from_wait(status: int) -> ExitStatus {
+        let sig = status & 0x7f;
+        if sig == 0 {
+            Exited((status >> 8) & 0xff)
+        } else if sig == 0x7f {
+            Stopped((status >> 8) & 0xff)
+        } else {
+            Signaled(sig, status & 0x80 != 0)
+        }
+    }
+
+    /// The exit code a shell would report for this status
+    pub fn code(&self) -> int {
+        match *self {
+            Exited(code) => code,
+            Signaled(sig, _) | Stopped(sig) => 128 + sig
+        }
+    }
+
+    pub fn describe(&self) -> ~str {
+        match *self {
+            Exited(code) => fmt!("exited with code %d", code),
+            Signaled(sig, core) => fmt!("killed by signal %s%s", sig::name(sig),
+                                        if core { " (core dumped)" } else { "" }),
+            Stopped(sig) => fmt!("stopped by signal %s", sig::name(sig))
+        }
     }
 }
 
@@ -134,6 +179,21 @@ pub fn fail(msg: &str) -> ! {
     abort()
 }
 
+#[inline]
+pub fn exit(code: int) -> ! {
+    unsafe {
+        syscall1(n::EXIT_GROUP, code);
+    }
+    loop {}
+}
+
+#[inline]
+pub fn getpid() -> pid_t {
+    unsafe {
+        syscall0(n::GETPID) as pid_t
+    }
+}
+
 #[inline]
 pub fn getuid() -> int {
     unsafe {
@@ -142,16 +202,81 @@ pub fn getuid() -> int {
 }
 
 #[inline]
-pub fn setuid(uid: int) {
+pub fn setuid(uid: int) -> err::Error {
+    unsafe {
+        cast::transmute(syscall1(n::SETUID, uid))
+    }
+}
+
+#[inline]
+pub fn setgid(gid: int) -> err::Error {
+    unsafe {
+        cast::transmute(syscall1(n::SETGID, gid))
+    }
+}
+
+#[inline]
+pub fn setresuid(ruid: int, euid: int, suid: int) -> err::Error {
+    unsafe {
+        cast::transmute(syscall3(n::SETRESUID, ruid, euid, suid))
+    }
+}
+
+#[inline]
+pub fn setresgid(rgid: int, egid: int, sgid: int) -> err::Error {
+    unsafe {
+        cast::transmute(syscall3(n::SETRESGID, rgid, egid, sgid))
+    }
+}
+
+#[inline]
+pub fn getresuid() -> (int, int, int) {
+    unsafe {
+        let mut ids = [-1u32, -1u32, -1u32];
+        let idsp : int = cast::transmute(&mut ids);
+        syscall3(n::GETRESUID, idsp, idsp + 4, idsp + 8);
+
+        (ids[0] as int, ids[1] as int, ids[2] as int)
+    }
+}
+
+#[inline]
+pub fn getresgid() -> (int, int, int) {
+    unsafe {
+        let mut ids = [-1u32, -1u32, -1u32];
+        let idsp : int = cast::transmute(&mut ids);
+        syscall3(n::GETRESGID, idsp, idsp + 4, idsp + 8);
+
+        (ids[0] as int, ids[1] as int, ids[2] as int)
+    }
+}
+
+#[inline]
+pub fn setgroups(gids: &[u32]) -> err::Error {
+    unsafe {
+        let (ptr, len) : (int, int) = cast::transmute(gids);
+        cast::transmute(syscall2(n::SETGROUPS, len, ptr))
+    }
+}
+
+#[inline]
+pub fn getgroups(out: &mut [u32]) -> int {
     unsafe {
-        syscall1(n::SETUID, uid);
+        let (ptr, len) : (int, int) = cast::transmute(out);
+        syscall2(n::GETGROUPS, len, ptr)
     }
 }
 
+/**
+ * Restricts thread `pid`, 0 being the calling thread, to the CPUs set in
+ * `mask`. Bit `n % 64` of `mask[n / 64]` stands for CPU `n`. The affinity is
+ * inherited across fork and execve.
+ */
 #[inline]
-pub fn setgid(gid: int) {
+pub fn sched_setaffinity(pid: pid_t, mask: &[u64]) -> err::Error {
     unsafe {
-        syscall1(n::SETGID, gid);
+        let (ptr, len) : (int, int) = cast::transmute(mask);
+        cast::transmute(syscall3(n::SCHED_SETAFFINITY, pid as int, len * 8, ptr))
     }
 }
 
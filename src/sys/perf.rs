@@ -0,0 +1,126 @@
+use sys::err::{ErrorResult, Err, Ok};
+use sys::io::raw;
+use sys::n;
+use sys::syscall5;
+
+use std::cast;
+
+pub static TYPE_HARDWARE            : u32 = 0;
+pub static TYPE_SOFTWARE            : u32 = 1;
+
+pub static COUNT_HW_CPU_CYCLES      : u64 = 0;
+pub static COUNT_HW_INSTRUCTIONS    : u64 = 1;
+pub static COUNT_HW_CACHE_MISSES    : u64 = 3;
+pub static COUNT_HW_BRANCH_MISSES   : u64 = 5;
+
+pub static COUNT_SW_TASK_CLOCK      : u64 = 1;
+pub static COUNT_SW_PAGE_FAULTS     : u64 = 2;
+pub static COUNT_SW_CONTEXT_SWITCHES: u64 = 3;
+
+pub static FORMAT_TOTAL_TIME_ENABLED: u64 = 1;
+pub static FORMAT_TOTAL_TIME_RUNNING: u64 = 2;
+
+// Bits of perf_event_attr's flags bitfield
+pub static ATTR_DISABLED            : u64 = 1 << 0;
+pub static ATTR_INHERIT             : u64 = 1 << 1;
+pub static ATTR_EXCLUDE_HV          : u64 = 1 << 6;
+pub static ATTR_ENABLE_ON_EXEC      : u64 = 1 << 12;
+
+pub static FLAG_PID_CGROUP          : int = 1 << 2;
+pub static FLAG_FD_CLOEXEC          : int = 1 << 3;
+
+/// The first published version of struct perf_event_attr, newer kernels
+/// treat the fields added since as zero.
+struct Attr {
+    kind: u32,
+    size: u32,
+    config: u64,
+    sample_period: u64,
+    sample_type: u64,
+    read_format: u64,
+    flags: u64,
+    wakeup_events: u32,
+    bp_type: u32,
+    config1: u64
+}
+
+static ATTR_SIZE_VER0 : u32 = 64;
+
+/**
+ * A counting perf event. It is read together with the time it was enabled
+ * and running for, so a count of an event that had to share the PMU with
+ * others can be scaled up to an estimate of the full count.
+ */
+#[unsafe_no_drop_flag]
+pub struct Counter {
+    priv fd: int
+}
+
+impl Counter {
+    /**
+     * Opens a counter for event `config` of type `kind`, with `attr_flags`
+     * from the `ATTR_*` bits. `pid` and `cpu` select what is counted as in
+     * perf_event_open(2), with `FLAG_PID_CGROUP` in `flags` `pid` is the file
+     * descriptor of a cgroup directory instead.
+     */
+    pub fn open(kind: u32, config: u64, attr_flags: u64, pid: int, cpu: int,
+                flags: int) -> ErrorResult<Counter> {
+        let attr = Attr {
+            kind: kind,
+            size: ATTR_SIZE_VER0,
+            config: config,
+            sample_period: 0,
+            sample_type: 0,
+            read_format: FORMAT_TOTAL_TIME_ENABLED | FORMAT_TOTAL_TIME_RUNNING,
+            flags: attr_flags,
+            wakeup_events: 0,
+            bp_type: 0,
+            config1: 0
+        };
+
+        unsafe {
+            let attrp : int = cast::transmute(&attr);
+            let fd = syscall5(n::PERF_EVENT_OPEN, attrp, pid, cpu, -1, flags | FLAG_FD_CLOEXEC);
+            if fd < 0 {
+                Err(cast::transmute(fd))
+            } else {
+                Ok(Counter { fd: fd })
+            }
+        }
+    }
+
+    /// The count so far, scaled if the event was not running all the time it
+    /// was enabled
+    pub fn read(&self) -> ErrorResult<u64> {
+        let mut buf = [0u8,..24];
+        let ret = raw::read(self.fd, buf);
+
+        unsafe {
+            if ret < 0 {
+                return Err(cast::transmute(ret));
+            }
+            let vals : [u64,..3] = cast::transmute(buf);
+            let (count, enabled, running) = (vals[0], vals[1], vals[2]);
+
+            if running == 0 {
+                Ok(0)
+            } else if running == enabled {
+                Ok(count)
+            } else {
+                Ok((count as f64 * enabled as f64 / running as f64) as u64)
+            }
+        }
+    }
+
+    pub fn fd(&self) -> int {
+        self.fd
+    }
+}
+
+impl Drop for Counter {
+    fn drop(&self) {
+        if self.fd != 0 {
+            raw::close(self.fd);
+        }
+    }
+}
@@ -1,3 +1,9 @@
+use sys::err::Error;
+use sys::n;
+use sys::syscall4;
+
+use std::cast;
+
 
 pub static HUP          : int  = 1;
 pub static INT          : int  = 2;
@@ -33,3 +39,44 @@ pub static POLL         : int  = 29;
 pub static PWR          : int  = 30;
 pub static SYS          : int  = 31;
 pub static SIGUNUSED    : int  = SYS;
+
+static NAMES : &'static [&'static str] = &[
+    "SIG0", "SIGHUP", "SIGINT", "SIGQUIT", "SIGILL", "SIGTRAP", "SIGABRT", "SIGBUS",
+    "SIGFPE", "SIGKILL", "SIGUSR1", "SIGSEGV", "SIGUSR2", "SIGPIPE", "SIGALRM", "SIGTERM",
+    "SIGSTKFLT", "SIGCHLD", "SIGCONT", "SIGSTOP", "SIGTSTP", "SIGTTIN", "SIGTTOU", "SIGURG",
+    "SIGXCPU", "SIGXFSZ", "SIGVTALRM", "SIGPROF", "SIGWINCH", "SIGIO", "SIGPWR", "SIGSYS"
+];
+
+#[inline]
+pub fn name(sig: int) -> &'static str {
+    if sig >= 0 && (sig as uint) < NAMES.len() {
+        NAMES[sig]
+    } else {
+        "Unknown Signal"
+    }
+}
+
+static SIG_BLOCK        : int = 0;
+static SIG_UNBLOCK      : int = 1;
+
+/// The signal set holding `sigs`, for `block` and `unblock`
+pub fn set(sigs: &[int]) -> u64 {
+    sigs.iter().fold(0u64, |set, &sig| set | 1 << (sig - 1))
+}
+
+/// Blocks the signals in `set`, they stay pending until unblocked
+pub fn block(set: u64) -> Error {
+    sigprocmask(SIG_BLOCK, set)
+}
+
+/// Unblocks the signals in `set`
+pub fn unblock(set: u64) -> Error {
+    sigprocmask(SIG_UNBLOCK, set)
+}
+
+fn sigprocmask(how: int, set: u64) -> Error {
+    unsafe {
+        let setp : *u64 = &set;
+        cast::transmute(syscall4(n::RT_SIGPROCMASK, how, setp as int, 0, 8))
+    }
+}
@@ -0,0 +1,95 @@
+use sys;
+use sys::err::{ErrorResult, Err, Ok};
+use sys::io::raw;
+use sys::n;
+use sys::{syscall2,syscall4};
+
+use std::cast;
+
+pub static CLOCK_MONOTONIC      : int = 1;
+
+pub static TFD_NONBLOCK         : int = 0x000800;
+pub static TFD_CLOEXEC          : int = 0x080000;
+pub static TFD_TIMER_ABSTIME    : int = 1;
+
+static NSEC_PER_SEC : u64 = 1_000_000_000;
+
+/**
+ * A periodic timer backed by a timerfd on the monotonic clock. Expirations
+ * are scheduled by the kernel relative to the first one, so the period does
+ * not drift with the time spent between calls to `wait`.
+ */
+#[unsafe_no_drop_flag]
+pub struct Timer {
+    priv fd: int,
+    priv overruns: u64
+}
+
+impl Timer {
+    pub fn new(interval_ns: u64) -> ErrorResult<Timer> {
+        unsafe {
+            let fd = syscall2(n::TIMERFD_CREATE, CLOCK_MONOTONIC, TFD_CLOEXEC);
+            if fd < 0 {
+                return Err(cast::transmute(fd));
+            }
+
+            // struct itimerspec { it_interval, it_value }
+            let first = sys::getclock() + interval_ns;
+            let spec = [
+                (interval_ns / NSEC_PER_SEC) as int, (interval_ns % NSEC_PER_SEC) as int,
+                (first / NSEC_PER_SEC) as int, (first % NSEC_PER_SEC) as int
+            ];
+            let specp : int = cast::transmute(&spec);
+
+            let ret = syscall4(n::TIMERFD_SETTIME, fd, TFD_TIMER_ABSTIME, specp, 0);
+            if ret < 0 {
+                raw::close(fd);
+                return Err(cast::transmute(ret));
+            }
+
+            Ok(Timer { fd: fd, overruns: 0 })
+        }
+    }
+
+    /**
+     * Blocks until the timer next expires. Returns the number of expirations
+     * that were missed since the previous call.
+     */
+    pub fn wait(&mut self) -> ErrorResult<u64> {
+        let mut buf = [0u8,..8];
+
+        let mut ret = -sys::err::EINTR;
+        while ret == -sys::err::EINTR {
+            ret = raw::read(self.fd, buf);
+        }
+
+        unsafe {
+            if ret < 0 {
+                return Err(cast::transmute(ret));
+            }
+
+            let expirations : u64 = cast::transmute(buf);
+            let missed = if expirations > 0 { expirations - 1 } else { 0 };
+            self.overruns += missed;
+
+            Ok(missed)
+        }
+    }
+
+    /// Total number of expirations missed over the life of the timer
+    pub fn overruns(&self) -> u64 {
+        self.overruns
+    }
+
+    pub fn fd(&self) -> int {
+        self.fd
+    }
+}
+
+impl Drop for Timer {
+    fn drop(&self) {
+        if self.fd != 0 {
+            raw::close(self.fd);
+        }
+    }
+}
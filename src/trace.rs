@@ -0,0 +1,487 @@
+/*!
+ * The binary trace format.
+ *
+ * A trace starts with the magic bytes `GBTR` and a version byte, followed by
+ * the run metadata: the command, the environment and the column names as
+ * counted lists of strings, the cgroup name as a string and the sampling
+ * interval as a varint. Strings are a varint byte length followed by the
+ * bytes.
+ *
+ * The rest of the file is a sequence of records, each starting with a tag
+ * byte:
+ *
+ * * `SAMPLE`: the time since the previous sample as a varint, a bitmap of the
+ *   columns that have a value, one bit per column starting from the low bit
+ *   of the first byte, then for each of those columns the zigzag encoded
+ *   difference from the column's previous value as a varint.
+ * * `NOTE`: the time since the start of the run as a varint and the message.
+ * * `SUMMARY`: a count, then a name and a typed value for each entry.
+ */
+
+use sys;
+use sys::io;
+use sys::err::{Ok, Err};
+use output;
+
+use std::{cast,str,vec};
+
+static MAGIC : &'static [u8] = bytes!("GBTR");
+static VERSION : u8 = 2;
+
+static SAMPLE   : u8 = 1;
+static NOTE     : u8 = 2;
+static SUMMARY  : u8 = 3;
+
+static V_INT    : u8 = 0;
+static V_FLOAT  : u8 = 1;
+static V_STR    : u8 = 2;
+static V_BOOL   : u8 = 3;
+static V_NULL   : u8 = 4;
+
+/// Writes a run as a binary trace, see the module documentation
+pub struct Writer {
+    priv prev_time: u64,
+    priv prev: ~[int],
+    priv present: ~[u8]
+}
+
+impl Writer {
+    pub fn new() -> Writer {
+        Writer { prev_time: 0, prev: ~[], present: ~[] }
+    }
+}
+
+impl output::Format for Writer {
+    fn begin(&mut self, out: &mut io::BufferedFile, info: &output::RunInfo) {
+        out.write_bytes(MAGIC);
+        out.write_bytes([VERSION]);
+
+        put_strs(out, info.command);
+        put_strs(out, info.env);
+        put_strs(out, info.columns);
+        put_str(out, info.cgroup);
+        put_varint(out, info.interval_us as u64);
+
+        self.prev = vec::from_elem(info.columns.len(), 0);
+        self.present = vec::from_elem((info.columns.len() + 7) / 8, 0u8);
+    }
+
+    fn sample(&mut self, out: &mut io::BufferedFile, time_ns: u64, values: &[Option<int>]) {
+        out.write_bytes([SAMPLE]);
+        put_varint(out, time_ns - self.prev_time);
+        self.prev_time = time_ns;
+
+        for b in self.present.mut_iter() {
+            *b = 0;
+        }
+        for (i, v) in values.iter().enumerate() {
+            if v.is_some() {
+                self.present[i / 8] |= 1 << (i % 8);
+            }
+        }
+        out.write_bytes(self.present);
+
+        for (i, v) in values.iter().enumerate() {
+            match *v {
+                Some(v) => {
+                    put_varint(out, zigzag((v - self.prev[i]) as i64));
+                    self.prev[i] = v;
+                }
+                None => ()
+            }
+        }
+    }
+
+    fn note(&mut self, out: &mut io::BufferedFile, time_ns: u64, msg: &str) {
+        out.write_bytes([NOTE]);
+        put_varint(out, time_ns);
+        put_str(out, msg);
+    }
+
+    fn finish(&mut self, out: &mut io::BufferedFile, summary: &[(&str, output::Value)]) {
+        out.write_bytes([SUMMARY]);
+        put_varint(out, summary.len() as u64);
+
+        for &(name, ref val) in summary.iter() {
+            put_str(out, name);
+            match *val {
+                output::Int(n) => {
+                    out.write_bytes([V_INT]);
+                    put_varint(out, zigzag(n));
+                }
+                output::Float(f) => {
+                    out.write_bytes([V_FLOAT]);
+                    let bits : [u8,..8] = unsafe { cast::transmute(f) };
+                    out.write_bytes(bits);
+                }
+                output::Str(ref s) => {
+                    out.write_bytes([V_STR]);
+                    put_str(out, *s);
+                }
+                output::Bool(b) => {
+                    out.write_bytes([V_BOOL, b as u8]);
+                }
+                output::Null => {
+                    out.write_bytes([V_NULL]);
+                }
+            }
+        }
+    }
+}
+
+fn zigzag(n: i64) -> u64 {
+    ((n << 1) ^ (n >> 63)) as u64
+}
+
+fn unzigzag(n: u64) -> i64 {
+    ((n >> 1) as i64) ^ -((n & 1) as i64)
+}
+
+fn put_varint(out: &mut io::BufferedFile, mut n: u64) {
+    let mut buf = [0u8,..10];
+    let mut len = 0;
+
+    while n >= 0x80 {
+        buf[len] = (n as u8 & 0x7f) | 0x80;
+        n >>= 7;
+        len += 1;
+    }
+    buf[len] = n as u8;
+
+    out.write_bytes(buf.slice_to(len + 1));
+}
+
+fn put_str(out: &mut io::BufferedFile, s: &str) {
+    put_varint(out, s.len() as u64);
+    out.write_str(s);
+}
+
+fn put_strs(out: &mut io::BufferedFile, items: &[~str]) {
+    put_varint(out, items.len() as u64);
+    for s in items.iter() {
+        put_str(out, *s);
+    }
+}
+
+/// A record read back from a trace
+pub enum Record {
+    Sample(u64, ~[Option<int>]),
+    Note(u64, ~str),
+    Summary(~[(~str, output::Value)])
+}
+
+/**
+ * Reads a binary trace. The whole file is loaded up front, `info` holds the
+ * run metadata and `next` returns the records in order. A corrupt trace is
+ * reported with `sys::fail`.
+ */
+pub struct Reader {
+    info: output::RunInfo,
+    priv path: ~str,
+    priv buf: ~[u8],
+    priv pos: uint,
+    priv time: u64,
+    priv prev: ~[int]
+}
+
+impl Reader {
+    pub fn open(path: &str) -> Reader {
+        let buf = match io::File::open(path, "r") {
+            Ok(f) => match f.read_to_end() {
+                Ok(b) => b,
+                Err(e) => sys::fail(fmt!("Could not read %s (%s)", path, e.msg()))
+            },
+            Err(e) => sys::fail(fmt!("Could not open %s (%s)", path, e.msg()))
+        };
+
+        let mut r = Reader {
+            info: output::RunInfo {
+                command: ~[],
+                env: ~[],
+                cgroup: ~"",
+                interval_us: 0,
+                columns: ~[]
+            },
+            path: path.to_owned(),
+            buf: buf,
+            pos: 0,
+            time: 0,
+            prev: ~[]
+        };
+
+        if r.buf.len() < 5 || r.buf.slice_to(4) != MAGIC {
+            sys::fail(fmt!("%s is not a grease-bench trace", path));
+        }
+        if r.buf[4] != VERSION {
+            sys::fail(fmt!("%s has unsupported trace version %u", path, r.buf[4] as uint));
+        }
+        r.pos = 5;
+
+        r.info.command = r.get_strs();
+        r.info.env = r.get_strs();
+        r.info.columns = r.get_strs();
+        r.info.cgroup = r.get_str();
+        r.info.interval_us = r.get_varint() as uint;
+        r.prev = vec::from_elem(r.info.columns.len(), 0);
+
+        r
+    }
+
+    pub fn next(&mut self) -> Option<Record> {
+        if self.pos >= self.buf.len() {
+            return None;
+        }
+
+        let tag = self.get_byte();
+        let rec = match tag {
+            SAMPLE => {
+                self.time += self.get_varint();
+
+                let present = self.pos;
+                self.pos += (self.prev.len() + 7) / 8;
+                if self.pos > self.buf.len() {
+                    self.corrupt("sample runs past the end of file");
+                }
+
+                let mut values = vec::with_capacity(self.prev.len());
+                for i in range(0, self.prev.len()) {
+                    if self.buf[present + i / 8] & (1 << (i % 8)) != 0 {
+                        self.prev[i] += unzigzag(self.get_varint()) as int;
+                        values.push(Some(self.prev[i]));
+                    } else {
+                        values.push(None);
+                    }
+                }
+                Sample(self.time, values)
+            }
+            NOTE => {
+                let t = self.get_varint();
+                Note(t, self.get_str())
+            }
+            SUMMARY => {
+                let n = self.get_varint() as uint;
+                let mut entries = vec::with_capacity(n);
+                for _ in range(0, n) {
+                    let name = self.get_str();
+                    let val = match self.get_byte() {
+                        V_INT => output::Int(unzigzag(self.get_varint())),
+                        V_FLOAT => {
+                            let mut bits = [0u8,..8];
+                            for i in range(0u, 8) {
+                                bits[i] = self.get_byte();
+                            }
+                            output::Float(unsafe { cast::transmute(bits) })
+                        }
+                        V_STR => output::Str(self.get_str()),
+                        V_BOOL => output::Bool(self.get_byte() != 0),
+                        V_NULL => output::Null,
+                        t => self.corrupt(fmt!("unknown value type %u", t as uint))
+                    };
+                    entries.push((name, val));
+                }
+                Summary(entries)
+            }
+            t => self.corrupt(fmt!("unknown record type %u", t as uint))
+        };
+
+        Some(rec)
+    }
+
+    fn corrupt(&self, what: &str) -> ! {
+        sys::fail(fmt!("Corrupt trace %s at byte %u: %s", self.path, self.pos, what))
+    }
+
+    fn get_byte(&mut self) -> u8 {
+        if self.pos >= self.buf.len() {
+            self.corrupt("unexpected end of file");
+        }
+        self.pos += 1;
+        self.buf[self.pos - 1]
+    }
+
+    fn get_varint(&mut self) -> u64 {
+        let mut n = 0u64;
+        let mut shift = 0;
+        loop {
+            let b = self.get_byte();
+            n |= (b & 0x7f) as u64 << shift;
+            if b & 0x80 == 0 {
+                return n;
+            }
+            shift += 7;
+            if shift >= 64 {
+                self.corrupt("varint too long");
+            }
+        }
+    }
+
+    fn get_str(&mut self) -> ~str {
+        let len = self.get_varint() as uint;
+        if self.pos + len > self.buf.len() {
+            self.corrupt("string runs past the end of file");
+        }
+        let s = unsafe {
+            str::raw::from_buf_len(vec::raw::to_ptr(self.buf.slice_from(self.pos)), len)
+        };
+        self.pos += len;
+        s
+    }
+
+    fn get_strs(&mut self) -> ~[~str] {
+        let n = self.get_varint() as uint;
+        let mut v = vec::with_capacity(n);
+        for _ in range(0, n) {
+            v.push(self.get_str());
+        }
+        v
+    }
+}
+
+/**
+ * The `convert` command: reads the binary trace `args[0]` and writes it to
+ * `args[1]` in the format given by `--format`, or picked from the output file
+ * name.
+ */
+pub fn convert(args: &[~str]) -> int {
+    let mut files = ~[];
+    let mut format = None;
+
+    let mut i = 0;
+    while i < args.len() {
+        let arg : &str = args[i];
+        if arg == "-f" || arg == "--format" {
+            i += 1;
+            if i >= args.len() {
+                convert_usage();
+            }
+            let f : &str = args[i];
+            format = match output::by_name(f) {
+                Some(f) => Some(f),
+                None => convert_usage()
+            };
+        } else if arg.starts_with("--format=") {
+            format = match output::by_name(arg.slice_from(9)) {
+                Some(f) => Some(f),
+                None => convert_usage()
+            };
+        } else {
+            files.push(arg);
+        }
+        i += 1;
+    }
+
+    if files.len() != 2 {
+        convert_usage();
+    }
+
+    let mut reader = Reader::open(files[0]);
+    let mut format = match format {
+        Some(f) => f,
+        None => output::for_path(files[1])
+    };
+    let mut out = match io::BufferedFile::open(files[1], "w") {
+        Ok(f) => f,
+        Err(e) => sys::fail(fmt!("Could not open %s (%s)", files[1], e.msg()))
+    };
+
+    format.begin(&mut out, &reader.info);
+    loop {
+        match reader.next() {
+            Some(Sample(t, values)) => format.sample(&mut out, t, values),
+            Some(Note(t, msg)) => format.note(&mut out, t, msg),
+            Some(Summary(entries)) => {
+                let summary : ~[(&str, output::Value)] =
+                    entries.iter().map(|&(ref n, ref v)| (n.as_slice(), v.clone())).collect();
+                format.finish(&mut out, summary);
+            }
+            None => break
+        }
+    }
+
+    0
+}
+
+fn convert_usage() -> ! {
+    io::println("Usage: grease-bench convert [--format csv|json|binary] TRACE OUTPUT");
+    sys::exit(2)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Reader, Writer, Sample, Note, Summary};
+    use output;
+    use output::Format;
+    use sys;
+    use sys::io;
+
+    #[test]
+    fn round_trip() {
+        let path = fmt!("/tmp/grease-bench-test-%d.gbt", sys::getpid() as int);
+        let info = output::RunInfo {
+            command: ~[~"make", ~"-j8"],
+            env: ~[~"CC=clang"],
+            cgroup: ~"bench-1",
+            interval_us: 100,
+            columns: ~[~"memory.usage_in_bytes", ~"pids.current"]
+        };
+        // The second and third samples differ by more than 2^62
+        let samples = ~[
+            (0u64, ~[Some(4096), None]),
+            (100_000u64, ~[Some(-(1 << 62) - 5), Some(3)]),
+            (250_000u64, ~[Some((1 << 62) + 7), Some(3)]),
+            (300_000u64, ~[None, None])
+        ];
+
+        {
+            let mut out = io::BufferedFile::open(path, "w").unwrap();
+            let mut w = Writer::new();
+            w.begin(&mut out, &info);
+            for &(t, ref values) in samples.iter() {
+                w.sample(&mut out, t, *values);
+            }
+            w.note(&mut out, 200_000, "OOM killer invoked");
+            w.finish(&mut out, [("exit_code", output::Int(-1)),
+                                ("mean", output::Float(1.5)),
+                                ("status", output::Str(~"exited")),
+                                ("timed_out", output::Bool(true)),
+                                ("failcnt", output::Null)]);
+        }
+
+        let mut r = Reader::open(path);
+        io::unlink(path);
+
+        assert_eq!(r.info.command, info.command);
+        assert_eq!(r.info.env, info.env);
+        assert_eq!(r.info.cgroup, info.cgroup);
+        assert_eq!(r.info.interval_us, info.interval_us);
+        assert_eq!(r.info.columns, info.columns);
+
+        for &(t, ref values) in samples.iter() {
+            match r.next() {
+                Some(Sample(rt, rv)) => {
+                    assert_eq!(rt, t);
+                    assert_eq!(rv, values.clone());
+                }
+                _ => fail!("expected a sample")
+            }
+        }
+        match r.next() {
+            Some(Note(t, msg)) => {
+                assert_eq!(t, 200_000);
+                assert_eq!(msg, ~"OOM killer invoked");
+            }
+            _ => fail!("expected a note")
+        }
+        match r.next() {
+            Some(Summary(entries)) => {
+                assert_eq!(entries, ~[(~"exit_code", output::Int(-1)),
+                                      (~"mean", output::Float(1.5)),
+                                      (~"status", output::Str(~"exited")),
+                                      (~"timed_out", output::Bool(true)),
+                                      (~"failcnt", output::Null)]);
+            }
+            _ => fail!("expected the summary")
+        }
+        assert!(r.next().is_none());
+    }
+}